@@ -0,0 +1,4148 @@
+//! Extracts files of chosen types out of zip, tar and 7z archives.
+//!
+//! This is the engine behind the Zip File Extractor GUI and its command-line
+//! mode. Fill in an [`ExtractOptions`] and call [`extract`]; log lines and
+//! progress arrive through the callback while the run is going. To run it in
+//! the background instead, iterate over an [`Extraction`].
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use encoding_rs::Encoding;
+use filetime::FileTime;
+use flate2::read::GzDecoder;
+use globset::{GlobBuilder, GlobMatcher};
+use rayon::prelude::*;
+use regex::{Regex, RegexBuilder};
+use sevenz_rust::{Password, SevenZReader};
+use zip::read::ZipArchive;
+use zip::result::ZipError;
+use zip::write::{SimpleFileOptions, ZipWriter};
+use zip::HasZipMetadata;
+
+use chrono::{Datelike, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+
+/// What to do when an extracted file would land on a path that already exists.
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum DuplicatePolicy {
+    Overwrite,
+    Rename,
+    Skip,
+    /// Ask for each collision through [`Message::Conflict`].
+    Ask,
+}
+
+/// The answer to a [`Message::Conflict`].
+pub struct ConflictChoice {
+    /// `Overwrite`, `Rename` or `Skip`; `Ask` is taken as `Skip`.
+    pub policy: DuplicatePolicy,
+    /// Use the same answer for every later collision in the run.
+    pub apply_to_all: bool,
+}
+
+/// One row of the extension-to-folder table used with `use_extension_folders`.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ExtensionFolder {
+    /// Comma-separated extensions without dots, e.g. "jpg, png".
+    pub extensions: String,
+    /// Folder inside the output directory those files go into, e.g. "Images".
+    pub folder: String,
+}
+
+/// Whether the extensions field lists what to extract or what to leave out.
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum FilterMode {
+    Include,
+    Exclude,
+}
+
+/// A change made to the name of every extracted file, before duplicates are
+/// checked for, so names that only become equal are still kept apart.
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum NameTransform {
+    None,
+    /// "Report Q1.PDF" becomes "report q1.pdf".
+    Lowercase,
+    /// "Report Q1.PDF" becomes "Report_Q1.PDF".
+    ReplaceSpaces,
+}
+
+impl NameTransform {
+    fn apply(self, name: &str) -> String {
+        match self {
+            NameTransform::None => name.to_string(),
+            NameTransform::Lowercase => name.to_lowercase(),
+            NameTransform::ReplaceSpaces => name.replace(' ', "_"),
+        }
+    }
+}
+
+/// How much of a run is written to the log.
+#[derive(PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Verbosity {
+    /// Only messages about whole archives and the summary.
+    Quiet,
+    /// Also warnings and errors about single entries.
+    Normal,
+    /// Also a line for every entry extracted or skipped.
+    Verbose,
+}
+
+/// Settings for a single extraction run. The GUI takes a snapshot of its
+/// settings into one of these so they can keep being edited while it works.
+#[derive(Clone)]
+pub struct ExtractOptions {
+    pub input_paths: Vec<String>,
+    pub output_path: String,
+    pub extensions: String,
+    pub filter_mode: FilterMode,
+    pub preserve_structure: bool,
+    pub duplicate_policy: DuplicatePolicy,
+    pub recursive: bool,
+    pub extract_nested: bool,
+    pub max_nested_depth: usize,
+    pub dry_run: bool,
+    pub case_sensitive: bool,
+    pub skip_macosx: bool,
+    pub min_size_kb: String,
+    pub max_size_kb: String,
+    pub modified_after: Option<NaiveDate>,
+    pub modified_before: Option<NaiveDate>,
+    pub name_pattern: String,
+    pub name_pattern_regex: bool,
+    pub output_per_zip: bool,
+    pub detect_by_content: bool,
+    pub content_types: String,
+    pub dated_subfolder: bool,
+    pub preserve_timestamps: bool,
+    pub worker_threads: usize,
+    pub buffer_size_kb: usize,
+    pub write_retries: u32,
+    pub source_prefix: bool,
+    pub delete_source: bool,
+    pub organize_by_extension: bool,
+    pub name_encoding: String,
+    pub abort_on_low_space: bool,
+    pub max_per_extension: usize,
+    pub verify_crc: bool,
+    pub delete_corrupt: bool,
+    pub output_zip: bool,
+    pub skip_unchanged: bool,
+    pub skip_unchanged_crc: bool,
+    pub verbosity: Verbosity,
+    pub recreate_symlinks: bool,
+    pub write_manifest: bool,
+    pub append_manifest: bool,
+    pub name_template: String,
+    pub max_total_entries: usize,
+    pub exclude_paths: String,
+    pub case_insensitive_names: bool,
+    pub max_compression_ratio: u64,
+    pub output_relative_to_exe: bool,
+    pub create_empty_dirs: bool,
+    pub allow_overlapping_output: bool,
+    pub use_extension_folders: bool,
+    pub extension_folders: Vec<ExtensionFolder>,
+    pub default_extension_folder: String,
+    pub name_transform: NameTransform,
+    pub skip_empty_files: bool,
+    pub test_integrity: bool,
+    pub require_empty_output: bool,
+    pub diff_output: bool,
+    pub low_priority: bool,
+    pub strip_components: usize,
+    pub skip_ungrouped_files: bool,
+    pub pause_below_free_mb: u64,
+    pub parent_prefix: bool,
+    pub password: String,
+    /// When set, only entries with these exact names are extracted.
+    pub selected_entries: Option<HashSet<String>>,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            input_paths: Vec::new(),
+            output_path: String::new(),
+            extensions: String::new(),
+            filter_mode: FilterMode::Include,
+            preserve_structure: false,
+            duplicate_policy: DuplicatePolicy::Rename,
+            recursive: false,
+            extract_nested: false,
+            max_nested_depth: 3,
+            dry_run: false,
+            case_sensitive: false,
+            skip_macosx: true,
+            min_size_kb: String::new(),
+            max_size_kb: String::new(),
+            modified_after: None,
+            modified_before: None,
+            name_pattern: String::new(),
+            name_pattern_regex: false,
+            output_per_zip: false,
+            detect_by_content: false,
+            content_types: String::new(),
+            dated_subfolder: false,
+            preserve_timestamps: true,
+            // Parallel reads and writes make a spinning disk seek back and forth,
+            // so one archive at a time is the safe default.
+            worker_threads: 1,
+            buffer_size_kb: 64,
+            write_retries: 3,
+            source_prefix: false,
+            delete_source: false,
+            organize_by_extension: false,
+            name_encoding: String::new(),
+            abort_on_low_space: false,
+            max_per_extension: 0,
+            verify_crc: false,
+            delete_corrupt: false,
+            output_zip: false,
+            skip_unchanged: false,
+            skip_unchanged_crc: false,
+            verbosity: Verbosity::Verbose,
+            recreate_symlinks: false,
+            write_manifest: false,
+            append_manifest: false,
+            name_template: String::new(),
+            max_total_entries: 0,
+            exclude_paths: String::new(),
+            case_insensitive_names: false,
+            max_compression_ratio: 1000,
+            output_relative_to_exe: false,
+            create_empty_dirs: false,
+            allow_overlapping_output: false,
+            use_extension_folders: false,
+            extension_folders: Vec::new(),
+            default_extension_folder: String::new(),
+            name_transform: NameTransform::None,
+            skip_empty_files: false,
+            test_integrity: false,
+            require_empty_output: false,
+            diff_output: false,
+            low_priority: false,
+            strip_components: 0,
+            skip_ungrouped_files: false,
+            pause_below_free_mb: 0,
+            parent_prefix: false,
+            password: String::new(),
+            selected_entries: None,
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// The read/write buffer size in bytes; at least 1 KB.
+    fn buffer_size(&self) -> usize {
+        self.buffer_size_kb.max(1).saturating_mul(1024)
+    }
+}
+
+/// Progress reports handed to the callback of [`extract`] during a run.
+pub enum Message {
+    /// A line of text for the log.
+    Log(String),
+    /// Overall progress across every entry of every archive in the run.
+    Progress { done: usize, total: usize },
+    /// Bytes written so far across the whole run, against the uncompressed size of
+    /// the entries that pass the extension filter. Used to estimate time remaining.
+    Bytes { written: u64, total: u64 },
+    /// Bytes written so far for the entry currently being extracted.
+    FileProgress {
+        name: String,
+        written: u64,
+        total: u64,
+    },
+    /// Something the user may want to look at, e.g. an entry that was refused.
+    Warning(String),
+    /// Something went wrong that the user should notice, e.g. an archive that
+    /// could not be opened. The run carries on with the next archive.
+    Error(String),
+    /// With [`DuplicatePolicy::Ask`], an entry would land on `path`, which already
+    /// exists. The worker waits until a choice is sent back through `reply`; if
+    /// `reply` is dropped unanswered, the entry is skipped.
+    Conflict {
+        path: PathBuf,
+        reply: mpsc::Sender<ConflictChoice>,
+    },
+    /// The archives hold more than `max_total_entries` entries. The run waits for
+    /// `true` through `reply` to go ahead; anything else, including dropping
+    /// `reply`, ends the run before anything is extracted.
+    ConfirmLargeRun {
+        entries: usize,
+        limit: usize,
+        reply: mpsc::Sender<bool>,
+    },
+    /// A file was written to disk.
+    Extracted(ExtractedFile),
+    /// The run has finished (or was cancelled); no further messages follow.
+    Done(Summary),
+}
+
+/// A file written during a run, as reported by [`Message::Extracted`].
+#[derive(Clone)]
+pub struct ExtractedFile {
+    pub path: PathBuf,
+    pub size: u64,
+    /// The archive the file came from, as shown in the log.
+    pub archive: PathBuf,
+    /// Path of the entry inside the archive.
+    pub entry: String,
+    /// CRC32 of the contents as written.
+    pub crc32: u32,
+    /// Whether a file was already at `path` and got overwritten.
+    pub replaced: bool,
+    /// Folders that were created to hold the file, deepest first.
+    pub created_dirs: Vec<PathBuf>,
+}
+
+/// Running count of processed archive entries for the current run, shared by
+/// the worker threads.
+struct Progress {
+    done: AtomicUsize,
+    total: usize,
+    bytes_written: AtomicU64,
+    bytes_total: u64,
+}
+
+impl Progress {
+    /// Counts `bytes` more written bytes and returns the update to report.
+    fn add_bytes(&self, bytes: u64) -> Message {
+        let written = self.bytes_written.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        Message::Bytes {
+            written,
+            total: self.bytes_total,
+        }
+    }
+
+    fn message(&self) -> Message {
+        Message::Progress {
+            done: self.done.load(Ordering::Relaxed),
+            total: self.total,
+        }
+    }
+
+    /// Counts one more processed entry and returns the update to report.
+    fn advance(&self) -> Message {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        Message::Progress {
+            done,
+            total: self.total,
+        }
+    }
+}
+
+/// The output path as a run uses it, with environment variables expanded (see
+/// [`expand_env_vars`]). With `relative_to_exe`, a relative path is taken from
+/// the folder the executable is in, so a copy of the tool on a USB stick writes
+/// next to itself on any machine.
+pub fn resolve_output_path(output_path: &str, relative_to_exe: bool) -> PathBuf {
+    let path = PathBuf::from(expand_env_vars(output_path.trim()));
+    if !relative_to_exe || path.is_absolute() {
+        return path;
+    }
+    match std::env::current_exe() {
+        Ok(exe) => exe.parent().map_or(path.clone(), |dir| dir.join(&path)),
+        Err(_) => path,
+    }
+}
+
+/// Replaces `%NAME%`, `$NAME` and `${NAME}` with the value of that environment
+/// variable, e.g. `%USERPROFILE%\Downloads` or `$HOME/archives`, so saved paths
+/// work for every user account. Variables that aren't set are left as typed.
+pub fn expand_env_vars(text: &str) -> String {
+    let mut expanded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(['%', '$']) {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        // The variable name and the length of the whole reference.
+        let (name, len) = if rest[start..].starts_with('%') {
+            after
+                .find('%')
+                .map_or(("", 1), |end| (&after[..end], end + 2))
+        } else if let Some(braced) = after.strip_prefix('{') {
+            braced
+                .find('}')
+                .map_or(("", 1), |end| (&braced[..end], end + 3))
+        } else {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            (&after[..end], end + 1)
+        };
+        match std::env::var(name).ok().filter(|_| !name.is_empty()) {
+            Some(value) => expanded.push_str(&value),
+            None => expanded.push_str(&rest[start..start + len]),
+        }
+        rest = &rest[start + len..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// One worker thread per CPU: about as many concurrent archives as pay off
+/// when the disks involved are SSDs.
+pub fn default_worker_threads() -> usize {
+    thread::available_parallelism().map_or(1, |count| count.get())
+}
+
+/// Runs an extraction, handing every log line and progress update to
+/// `progress_callback` as it happens. Used by both the GUI and the headless
+/// command-line mode. Returns the totals of the run, or the error that stopped
+/// it (e.g. an invalid input path); problems with single archives are reported
+/// through the callback and counted in the summary instead.
+pub fn extract(
+    options: &ExtractOptions,
+    progress_callback: impl FnMut(Message) + Send,
+) -> Result<Summary, Box<dyn Error>> {
+    extract_with_cancel(options, &AtomicBool::new(false), progress_callback)
+}
+
+/// Like [`extract`], but the run stops early, without removing already-extracted
+/// files, once `cancel_flag` is set.
+///
+/// With more than one worker thread, messages from different archives arrive
+/// interleaved; the callback is never called from two threads at once.
+pub fn extract_with_cancel(
+    options: &ExtractOptions,
+    cancel_flag: &AtomicBool,
+    progress_callback: impl FnMut(Message) + Send,
+) -> Result<Summary, Box<dyn Error>> {
+    extract_with_pause(
+        options,
+        cancel_flag,
+        &PauseControl::default(),
+        progress_callback,
+    )
+}
+
+/// Like [`extract_with_cancel`], but the run can also be held with `pause`.
+/// While paused, workers stop before their next entry or chunk of data, so
+/// nothing is written until [`PauseControl::resume`] is called. Cancelling
+/// still works while paused.
+pub fn extract_with_pause(
+    options: &ExtractOptions,
+    cancel_flag: &AtomicBool,
+    pause: &PauseControl,
+    progress_callback: impl FnMut(Message) + Send,
+) -> Result<Summary, Box<dyn Error>> {
+    // The archive code passes the reporter around by shared reference, from
+    // several threads at once.
+    let callback = Mutex::new(progress_callback);
+    let report = |message| {
+        if let Ok(mut callback) = callback.lock() {
+            callback(message);
+        }
+    };
+    let summary = extract_files(options, cancel_flag, pause, &report)?;
+    report(Message::Done(summary.clone()));
+    Ok(summary)
+}
+
+/// A run on a background thread whose messages are read by iterating over it,
+/// for programs that embed the extractor and show progress their own way.
+/// Every written file arrives as a [`Message::Extracted`] with its source
+/// archive, entry name and size. Iteration ends once the run is over, after
+/// [`Message::Done`], or after a [`Message::Error`] if the run couldn't go on
+/// (e.g. because an input doesn't exist).
+///
+/// Dropping it cancels the run.
+pub struct Extraction {
+    messages: mpsc::Receiver<Message>,
+    cancel_flag: Arc<AtomicBool>,
+    pause: Arc<PauseControl>,
+}
+
+impl Extraction {
+    pub fn start(options: ExtractOptions) -> Self {
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+        let pause = Arc::new(PauseControl::default());
+        let (sender, messages) = mpsc::channel();
+        let worker_cancel = Arc::clone(&cancel_flag);
+        let worker_pause = Arc::clone(&pause);
+        thread::spawn(move || {
+            let result = extract_with_pause(&options, &worker_cancel, &worker_pause, |message| {
+                let _ = sender.send(message);
+            });
+            if let Err(err) = result {
+                let _ = sender.send(Message::Error(err.to_string()));
+            }
+        });
+        Extraction {
+            messages,
+            cancel_flag,
+            pause,
+        }
+    }
+
+    /// Asks the run to stop. Messages sent before it did can still be read.
+    pub fn cancel(&self) {
+        self.cancel_flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn pause_control(&self) -> &PauseControl {
+        &self.pause
+    }
+
+    /// The next message if one is waiting, without blocking, e.g. for polling
+    /// from a GUI's frame loop. `Err(Disconnected)` once the run is over.
+    pub fn try_next(&self) -> Result<Message, mpsc::TryRecvError> {
+        self.messages.try_recv()
+    }
+}
+
+impl Iterator for Extraction {
+    type Item = Message;
+
+    fn next(&mut self) -> Option<Message> {
+        self.messages.recv().ok()
+    }
+}
+
+impl Drop for Extraction {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Pauses and resumes a run started with [`extract_with_pause`]; shared between
+/// the thread that controls the run and the workers.
+#[derive(Default)]
+pub struct PauseControl {
+    paused: Mutex<bool>,
+    resumed: Condvar,
+    /// Set when the user resumed a pause for low disk space while space was
+    /// still low; no further such pauses happen until space recovers.
+    low_space_overridden: AtomicBool,
+}
+
+/// How often a paused worker wakes up to check whether the run was cancelled.
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How often free space is checked while a run is paused for lack of it.
+const LOW_SPACE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How often free space is checked while a single file is being written.
+const LOW_SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+impl PauseControl {
+    pub fn pause(&self) {
+        *self.paused.lock().unwrap_or_else(PoisonError::into_inner) = true;
+    }
+
+    pub fn resume(&self) {
+        *self.paused.lock().unwrap_or_else(PoisonError::into_inner) = false;
+        self.resumed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Blocks while the run is paused, returning once it is resumed or `cancel_flag`
+    /// is set.
+    fn wait_while_paused(&self, cancel_flag: &AtomicBool) {
+        let mut paused = self.paused.lock().unwrap_or_else(PoisonError::into_inner);
+        while *paused && !cancel_flag.load(Ordering::Relaxed) {
+            paused = self
+                .resumed
+                .wait_timeout(paused, PAUSE_POLL_INTERVAL)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+    }
+}
+
+/// Performs the extraction work, sending progress messages through `report`.
+fn extract_files(
+    options: &ExtractOptions,
+    cancel_flag: &AtomicBool,
+    pause: &PauseControl,
+    report: &(dyn Fn(Message) + Sync),
+) -> Result<Summary, Box<dyn Error>> {
+    // Applies to the calling thread, which does the work when running one
+    // archive at a time.
+    if options.low_priority {
+        if let Err(err) = lower_thread_priority() {
+            report(Message::Warning(format!(
+                "Could not lower the priority of the extraction: {}",
+                err
+            )));
+        }
+    }
+    if options.test_integrity {
+        return test_archives(options, cancel_flag, pause, report);
+    }
+    // A diff is a dry run that also looks at what is already in the output.
+    if options.diff_output && !options.dry_run {
+        let options = ExtractOptions {
+            dry_run: true,
+            ..options.clone()
+        };
+        return extract_files(&options, cancel_flag, pause, report);
+    }
+    if options.diff_output && options.output_zip {
+        return Err(
+            "Comparing with the existing output needs an output folder; it can't be \
+             used when writing into a zip."
+                .into(),
+        );
+    }
+    let output_target = resolve_output_path(&options.output_path, options.output_relative_to_exe);
+    if options.output_relative_to_exe || output_target != Path::new(options.output_path.trim()) {
+        report(Message::Log(format!(
+            "Output resolved to: {}\n",
+            output_target.display()
+        )));
+    }
+    if !options.dry_run {
+        if let Some(input) = overlapping_input(&options.input_paths, &output_target) {
+            let text = format!(
+                "Output {} overlaps input folder {}, so its files could be picked up as input.",
+                output_target.display(),
+                input.display()
+            );
+            if !options.allow_overlapping_output {
+                return Err(
+                    format!("{} Choose another output or allow overlapping paths.", text).into(),
+                );
+            }
+            report(Message::Warning(text));
+        }
+    }
+    // When writing into a zip, output paths are built relative to the root of
+    // that zip rather than to a directory.
+    let mut output_path = if options.output_zip {
+        PathBuf::new()
+    } else {
+        output_target.clone()
+    };
+    // The configured folder, not the dated one inside it, which is always new.
+    if options.require_empty_output && !options.output_zip {
+        let existing = count_dir_entries(&output_target)?;
+        if existing > 0 {
+            return Err(format!(
+                "Output directory {} is not empty ({} existing {}). Choose an empty folder \
+                 or turn off \"Require empty output directory\".",
+                output_target.display(),
+                existing,
+                if existing == 1 { "entry" } else { "entries" }
+            )
+            .into());
+        }
+    }
+    if options.dated_subfolder {
+        let stamp = Local::now().format("%Y-%m-%d_%H-%M-%S").to_string();
+        output_path.push(stamp);
+        report(Message::Log(format!(
+            "Extracting into: {}\n",
+            output_path.display()
+        )));
+    }
+    let mut zip_output = None;
+    if options.diff_output {
+        report(Message::Log(
+            "Comparing with the existing output: no files will be written.\n".to_string(),
+        ));
+    } else if options.dry_run {
+        report(Message::Log(
+            "Dry run: no files will be written.\n".to_string(),
+        ));
+    } else if options.output_zip {
+        let mut zip_path = output_target.clone();
+        if zip_path.exists() {
+            zip_path = match existing_zip_policy(&zip_path, options, report) {
+                DuplicatePolicy::Overwrite => zip_path,
+                DuplicatePolicy::Rename => next_free_path(&zip_path, Path::exists),
+                _ => {
+                    return Err(format!(
+                        "Output zip {} already exists. Choose another name, or set duplicates \
+                         to overwrite or rename.",
+                        zip_path.display()
+                    )
+                    .into())
+                }
+            };
+        }
+        if let Some(parent) = zip_path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        report(Message::Log(format!(
+            "Writing into zip: {}\n",
+            zip_path.display()
+        )));
+        zip_output = Some(Mutex::new(ZipOutput {
+            writer: ZipWriter::new(File::create(&zip_path)?),
+            names: HashSet::new(),
+        }));
+    } else {
+        check_output_writable(&output_path)
+            .map_err(|err| format!("Cannot write to output directory: {}", err))?;
+    }
+
+    // Split the extensions string into a vector.
+    // If the field is left empty, the vector will be empty.
+    let filter_exts: Vec<String> = options
+        .extensions
+        .split(',')
+        .map(|s| s.trim().trim_start_matches('.'))
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            if options.case_sensitive {
+                s.to_string()
+            } else {
+                s.to_lowercase()
+            }
+        })
+        .collect();
+
+    // Types wanted when detecting by content, matched against the detected
+    // extension, MIME type or MIME category.
+    let content_types: Vec<String> = options
+        .content_types
+        .split(',')
+        .map(|s| s.trim().trim_start_matches('.').to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect();
+
+    // Log which filter is in effect for this run.
+    if options.detect_by_content {
+        let wanted = if content_types.is_empty() {
+            "any type".to_string()
+        } else {
+            content_types.join(", ")
+        };
+        report(Message::Log(format!(
+            "Detecting file types by content, extracting: {}\n",
+            wanted
+        )));
+    } else if filter_exts.is_empty() {
+        report(Message::Log(
+            "No file extensions provided, extracting all files.\n".to_string(),
+        ));
+    } else {
+        let mode = match options.filter_mode {
+            FilterMode::Include => "only extracting",
+            FilterMode::Exclude => "extracting everything except",
+        };
+        report(Message::Log(format!(
+            "Filter mode: {} {}\n",
+            mode,
+            filter_exts.join(", ")
+        )));
+    }
+
+    let min_size =
+        parse_size_kb(&options.min_size_kb).map_err(|err| format!("Min size: {}", err))?;
+    let max_size =
+        parse_size_kb(&options.max_size_kb).map_err(|err| format!("Max size: {}", err))?;
+
+    // Compile the name pattern once so a typo is reported up front rather than
+    // for every entry.
+    let name_pattern = options.name_pattern.trim();
+    let name_filter = if name_pattern.is_empty() {
+        None
+    } else if options.name_pattern_regex {
+        let regex = RegexBuilder::new(name_pattern)
+            .case_insensitive(options.case_insensitive_names)
+            .build()
+            .map_err(|err| format!("Invalid regex \"{}\": {}", name_pattern, err))?;
+        report(Message::Log(format!(
+            "Only extracting entries matching regex: {}\n",
+            name_pattern
+        )));
+        Some(NameFilter::Regex(regex))
+    } else {
+        let glob = GlobBuilder::new(name_pattern)
+            .case_insensitive(options.case_insensitive_names)
+            .build()
+            .map_err(|err| format!("Invalid name pattern \"{}\": {}", name_pattern, err))?;
+        report(Message::Log(format!(
+            "Only extracting files named like: {}\n",
+            name_pattern
+        )));
+        Some(NameFilter::Glob(glob.compile_matcher()))
+    };
+
+    let name_encoding = parse_name_encoding(&options.name_encoding)?;
+    if let Some(encoding) = name_encoding {
+        report(Message::Log(format!(
+            "Decoding entry names as {}\n",
+            encoding.name()
+        )));
+    }
+
+    // Entry names use '/' inside archives, whichever way the fragments were typed.
+    let exclude_paths: Vec<String> = options
+        .exclude_paths
+        .split(',')
+        .map(|fragment| fragment.trim().replace('\\', "/"))
+        .filter(|fragment| !fragment.is_empty())
+        .collect();
+    if !exclude_paths.is_empty() {
+        report(Message::Log(format!(
+            "Excluding entries whose path contains: {}\n",
+            exclude_paths.join(", ")
+        )));
+    }
+
+    let extension_folders = if options.use_extension_folders {
+        let folders = ExtensionFolders::parse(
+            &options.extension_folders,
+            &options.default_extension_folder,
+            options.skip_ungrouped_files,
+        )?;
+        report(Message::Log(format!(
+            "Sorting into folders by extension: {}\n",
+            folders.describe()
+        )));
+        Some(folders)
+    } else {
+        None
+    };
+
+    let template = options.name_template.trim();
+    let name_template = if template.is_empty() {
+        None
+    } else {
+        let parsed = NameTemplate::parse(template)
+            .map_err(|err| format!("Invalid name template \"{}\": {}", template, err))?;
+        report(Message::Log(format!("Naming files as: {}\n", template)));
+        Some(parsed)
+    };
+
+    // Downloaded archives live in temp files until the run ends; dropping these
+    // removes them.
+    let (archives, _downloads) = collect_inputs(options, cancel_flag, report)?;
+
+    // Scan every archive up front so progress can be reported against the whole
+    // run rather than per archive. Archives that can't be read here simply
+    // contribute nothing; the real error surfaces when processing.
+    let counts_toward_bytes = |name: &str| {
+        if options.skip_macosx && name.contains("__MACOSX") {
+            return false;
+        }
+        let normalized = name.replace('\\', "/");
+        if exclude_paths
+            .iter()
+            .any(|fragment| normalized.contains(fragment.as_str()))
+        {
+            return false;
+        }
+        options.detect_by_content
+            || should_extract(
+                Path::new(name),
+                &filter_exts,
+                options.filter_mode,
+                options.case_sensitive,
+            )
+    };
+    let mut total = 0;
+    let mut bytes_total = 0;
+    let mut compression = CompressionStats::default();
+    for archive in &archives {
+        let Some(scan) = scan_archive(&archive.path, archive.kind, &counts_toward_bytes) else {
+            continue;
+        };
+        total += scan.entries;
+        bytes_total += scan.matching_bytes;
+        if let Some(stats) = scan.compression {
+            if let Some(text) = stats.describe() {
+                report(Message::Log(format!(
+                    "{}: {}\n",
+                    archive.shown_path.display(),
+                    text
+                )));
+            }
+            compression.add(&stats);
+        }
+    }
+
+    // Warn (or stop) before writing anything if the output obviously won't fit.
+    if !options.dry_run {
+        if let Some(free) = available_space(&output_target) {
+            if bytes_total > free {
+                let text = format!(
+                    "The matching entries need about {}, but only {} is free on the output drive.",
+                    format_bytes(bytes_total),
+                    format_bytes(free)
+                );
+                if options.abort_on_low_space {
+                    return Err(text.into());
+                }
+                report(Message::Warning(text));
+            }
+        }
+    }
+
+    // Guards against a mis-picked folder turning into a huge run.
+    let limit = options.max_total_entries;
+    if limit > 0 && total > limit {
+        let (reply, answer) = mpsc::channel();
+        report(Message::ConfirmLargeRun {
+            entries: total,
+            limit,
+            reply,
+        });
+        if answer.recv() != Ok(true) {
+            report(Message::Log(format!(
+                "Run cancelled: {} entries is more than the limit of {}.\n",
+                total, limit
+            )));
+            return Ok(Summary::default());
+        }
+    }
+
+    let progress = Progress {
+        done: AtomicUsize::new(0),
+        total,
+        bytes_written: AtomicU64::new(0),
+        bytes_total,
+    };
+    report(progress.message());
+    let claim_lock = Mutex::new(HashSet::new());
+    let conflict_answer = Mutex::new(None);
+    let manifest = Mutex::new(Vec::new());
+    let per_extension = Mutex::new(HashMap::new());
+
+    // Each archive gets its own context and tally; the tallies are added up
+    // once every archive is done. A bad archive is logged and counted as
+    // failed; the rest of the batch still runs.
+    let run_archive = |archive: &ArchiveInput| -> Summary {
+        if cancel_flag.load(Ordering::Relaxed) {
+            return Summary::default();
+        }
+        let mut ctx = ExtractContext {
+            options,
+            filter_exts: &filter_exts,
+            content_types: &content_types,
+            output_dir: output_path.clone(),
+            current_archive: archive.shown_path.clone(),
+            cancel_flag,
+            pause,
+            report,
+            progress: &progress,
+            summary: Summary::default(),
+            min_size,
+            max_size,
+            name_filter: name_filter.as_ref(),
+            claim_lock: &claim_lock,
+            conflict_answer: &conflict_answer,
+            manifest: &manifest,
+            per_extension: &per_extension,
+            zip_output: zip_output.as_ref(),
+            name_encoding,
+            name_template: name_template.as_ref(),
+            extension_folders: extension_folders.as_ref(),
+            exclude_paths: &exclude_paths,
+            entry_index: 0,
+            output_target: &output_target,
+        };
+        let result = process_one_archive(archive, &output_path, &mut ctx);
+        match result {
+            Err(err) => {
+                report(Message::Error(format!(
+                    "Failed to open {}: {}",
+                    archive.shown_path.display(),
+                    err
+                )));
+                ctx.summary.archives_failed += 1;
+            }
+            // With several archives, shows what each one contributed.
+            Ok(()) if archives.len() > 1 && !cancel_flag.load(Ordering::Relaxed) => {
+                let counts = &ctx.summary;
+                let text = if options.diff_output {
+                    format!(
+                        "{} new, {} changed, {} identical",
+                        counts.files_new, counts.files_changed, counts.files_identical
+                    )
+                } else {
+                    format!(
+                        "{} {}, {} skipped, {} failed",
+                        counts.files_extracted,
+                        if options.dry_run {
+                            "to extract"
+                        } else {
+                            "extracted"
+                        },
+                        counts.files_skipped,
+                        counts.files_failed
+                    )
+                };
+                report(Message::Log(format!(
+                    "{}: {}\n",
+                    archive.shown_path.display(),
+                    text
+                )));
+            }
+            Ok(()) => {}
+        }
+        if !cancel_flag.load(Ordering::Relaxed) {
+            ctx.summary.archives_processed += 1;
+            // Any failure, even of a single entry, keeps the source so nothing is lost.
+            let clean = ctx.summary.archives_failed == 0 && !ctx.summary.keep_source;
+            if options.delete_source && !options.dry_run && clean {
+                match fs::remove_file(&archive.path) {
+                    Ok(()) => report(Message::Log(format!(
+                        "Deleted source archive: {}\n",
+                        archive.path.display()
+                    ))),
+                    Err(err) => report(Message::Warning(format!(
+                        "Could not delete source archive {}: {}",
+                        archive.path.display(),
+                        err
+                    ))),
+                }
+            } else if options.delete_source && !options.dry_run && ctx.summary.keep_source {
+                report(Message::Log(format!(
+                    "Kept source archive, since some of its entries were not extracted: {}\n",
+                    archive.path.display()
+                )));
+            }
+        }
+        ctx.summary
+    };
+
+    let workers = options.worker_threads.clamp(1, archives.len().max(1));
+    let summaries: Vec<Summary> = if workers == 1 {
+        archives.iter().map(run_archive).collect()
+    } else {
+        let low_priority = options.low_priority;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(workers)
+            .start_handler(move |_| {
+                if low_priority {
+                    let _ = lower_thread_priority();
+                }
+            })
+            .build()?;
+        pool.install(|| archives.par_iter().map(run_archive).collect())
+    };
+    let mut summary = Summary::default();
+    for archive_summary in &summaries {
+        summary.add(archive_summary);
+    }
+    // Finish the zip even after a cancel, so what was added so far is readable.
+    if let Some(zip_output) = zip_output {
+        let zip_output = zip_output
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        zip_output.writer.finish()?;
+    }
+    // Also written after a cancel, so the files already extracted are on record.
+    if options.write_manifest && !options.dry_run && !options.output_zip {
+        let mut manifest_path = output_path.join(MANIFEST_FILE_NAME);
+        // An extracted file may have taken the name; it is never overwritten.
+        let claimed = claim_lock
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        if claimed.contains(&manifest_path) {
+            manifest_path = next_free_path(&manifest_path, |candidate| {
+                claimed.contains(candidate) || candidate.exists()
+            });
+        }
+        let files = manifest
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+        match write_manifest(&manifest_path, &files, options.append_manifest) {
+            Ok(()) => report(Message::Log(format!(
+                "Wrote manifest: {}\n",
+                manifest_path.display()
+            ))),
+            Err(err) => report(Message::Warning(format!(
+                "Could not write manifest {}: {}",
+                manifest_path.display(),
+                err
+            ))),
+        }
+    }
+    if cancel_flag.load(Ordering::Relaxed) {
+        return Ok(summary);
+    }
+
+    if options.diff_output {
+        report(Message::Log(format!(
+            "Comparison completed: {} new, {} changed, {} identical.\n",
+            summary.files_new, summary.files_changed, summary.files_identical
+        )));
+    } else if options.dry_run {
+        report(Message::Log(format!(
+            "Dry run completed: {} file(s), {} would be written.\n",
+            summary.files_extracted,
+            format_bytes(summary.bytes_written)
+        )));
+    } else if summary.archives_failed > 0 {
+        report(Message::Log(format!(
+            "Extraction completed with {} failed archive(s).\n",
+            summary.archives_failed
+        )));
+    } else if summary.files_failed > 0 {
+        report(Message::Log(format!(
+            "Extraction completed with {} failed file(s).\n",
+            summary.files_failed
+        )));
+    } else {
+        report(Message::Log(
+            "Extraction completed successfully.\n".to_string(),
+        ));
+    }
+    if !options.dry_run {
+        report(Message::Log(format!(
+            "Summary: {} archive(s) processed ({} failed), {} file(s) extracted ({} failed), \
+             {} skipped by filter, {} written.\n",
+            summary.archives_processed,
+            summary.archives_failed,
+            summary.files_extracted,
+            summary.files_failed,
+            summary.files_skipped,
+            format_bytes(summary.bytes_written)
+        )));
+    }
+    if summary.run_duplicates > 0 {
+        report(Message::Log(format!(
+            "{} file(s) had the same output path as one from earlier in the run; handled as \
+             duplicates.\n",
+            summary.run_duplicates
+        )));
+    }
+    if let Some(text) = compression.describe() {
+        report(Message::Log(format!("Matching zip entries: {}\n", text)));
+    }
+    Ok(summary)
+}
+
+/// The first input folder that `output` lies inside of, or that lies inside
+/// `output`. Archive inputs are not checked: extracting next to an archive is
+/// the usual case and can't feed the run its own output.
+fn overlapping_input(input_paths: &[String], output: &Path) -> Option<PathBuf> {
+    let output = canonicalize_lenient(output);
+    input_paths
+        .iter()
+        .map(|input| PathBuf::from(expand_env_vars(input.trim())))
+        .filter(|input| input.is_dir())
+        .find(|input| {
+            let input = canonicalize_lenient(input);
+            output.starts_with(&input) || input.starts_with(&output)
+        })
+}
+
+/// `path` with symlinks and `..` resolved, even if it doesn't exist yet: its
+/// nearest existing parent is canonicalized and the rest appended.
+fn canonicalize_lenient(path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    for existing in absolute.ancestors() {
+        if let Ok(mut canonical) = existing.canonicalize() {
+            let rest = absolute.strip_prefix(existing).unwrap_or(Path::new(""));
+            for component in rest.components() {
+                match component {
+                    Component::ParentDir => {
+                        canonical.pop();
+                    }
+                    Component::Normal(part) => canonical.push(part),
+                    _ => {}
+                }
+            }
+            return canonical;
+        }
+    }
+    absolute
+}
+
+/// Windows refuses paths longer than `MAX_PATH` (260 characters) unless they are
+/// given in the `\\?\` form, which lifts the limit. There, a long output path is
+/// made absolute and turned into that form; it is returned unchanged otherwise.
+fn long_path(path: PathBuf) -> PathBuf {
+    // Folders have to leave room for an 8.3 file name, hence a little below 260.
+    const LONG_PATH_START: usize = 248;
+    if !cfg!(windows) {
+        return path;
+    }
+    // `absolute` also resolves `.` and `..`, which the `\\?\` form takes literally.
+    let Ok(absolute) = std::path::absolute(&path) else {
+        return path;
+    };
+    let Some(text) = absolute.to_str() else {
+        return path;
+    };
+    if text.len() < LONG_PATH_START || text.starts_with(r"\\?\") {
+        return path;
+    }
+    match text.strip_prefix(r"\\") {
+        // A network path, \\server\share\...
+        Some(unc) => PathBuf::from(format!(r"\\?\UNC\{}", unc)),
+        None => PathBuf::from(format!(r"\\?\{}", text)),
+    }
+}
+
+/// Creates the output directory if needed and writes (and removes) a small
+/// probe file in it, so a read-only or full location is reported before any
+/// archive is opened instead of partway through the run.
+fn check_output_writable(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(format!(".zip_file_extractor_probe_{}", std::process::id()));
+    let written = File::create(&probe).and_then(|mut file| file.write_all(b"probe"));
+    let _ = fs::remove_file(&probe);
+    written
+}
+
+/// Number of files and folders directly inside `dir`, or 0 if it doesn't
+/// exist yet.
+fn count_dir_entries(dir: &Path) -> io::Result<usize> {
+    match fs::read_dir(dir) {
+        Ok(entries) => Ok(entries.count()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+/// Name of the file `write_manifest` creates in the output directory.
+const MANIFEST_FILE_NAME: &str = "manifest.csv";
+
+/// Writes one CSV row per extracted file to `path`, after a header row. With
+/// `append`, rows are added to an existing manifest and the header is only
+/// written if the file is new.
+fn write_manifest(path: &Path, files: &[ExtractedFile], append: bool) -> io::Result<()> {
+    let write_header = !append || !path.exists();
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)?;
+    let mut writer = BufWriter::new(file);
+    if write_header {
+        writeln!(writer, "source_archive,entry_path,output_path,size,crc32")?;
+    }
+    for file in files {
+        writeln!(
+            writer,
+            "{},{},{},{},{:08x}",
+            csv_field(&file.archive.display().to_string()),
+            csv_field(&file.entry),
+            csv_field(&file.path.display().to_string()),
+            file.size,
+            file.crc32
+        )?;
+    }
+    writer.flush()
+}
+
+/// Quotes a CSV field if it holds a comma, quote or line break.
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Extracts one archive of the run into its output folder.
+fn process_one_archive(
+    archive: &ArchiveInput,
+    output_path: &Path,
+    ctx: &mut ExtractContext,
+) -> Result<(), Box<dyn Error>> {
+    let options = ctx.options;
+    let report = ctx.report;
+    if options.output_per_zip {
+        ctx.output_dir = output_path.join(archive_stem(&archive.path));
+        if !options.dry_run && !options.output_zip {
+            fs::create_dir_all(&ctx.output_dir)?;
+        }
+    }
+    match archive.kind {
+        ArchiveKind::Zip => {
+            report(Message::Log(format!(
+                "Processing zip file: {}\n",
+                archive.shown_path.display()
+            )));
+            process_zip_file_thread(&archive.path, ctx)
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            report(Message::Log(format!(
+                "Processing tar file: {}\n",
+                archive.shown_path.display()
+            )));
+            let gzipped = archive.kind == ArchiveKind::TarGz;
+            process_tar_file_thread(&archive.path, gzipped, ctx)
+        }
+        ArchiveKind::SevenZ => {
+            report(Message::Log(format!(
+                "Processing 7z file: {}\n",
+                archive.shown_path.display()
+            )));
+            process_7z_file_thread(&archive.path, ctx)
+        }
+    }
+}
+
+/// Totals accumulated over a run.
+#[derive(Clone, Default)]
+pub struct Summary {
+    pub archives_processed: usize,
+    /// Archives that could not be processed, e.g. because of a missing password.
+    pub archives_failed: usize,
+    pub files_extracted: usize,
+    /// Entries that could not be extracted; the rest of their archive still was.
+    pub files_failed: usize,
+    /// Files left out by the extension filter.
+    pub files_skipped: usize,
+    /// Entries whose output path was already used by an earlier one in the run,
+    /// e.g. the same file in two archives.
+    pub run_duplicates: usize,
+    pub bytes_written: u64,
+    /// With `diff_output`, entries whose output path doesn't exist yet.
+    pub files_new: usize,
+    /// With `diff_output`, entries whose output file differs in size or CRC.
+    pub files_changed: usize,
+    /// With `diff_output`, entries whose output file already matches.
+    pub files_identical: usize,
+    /// Some entry failed, was refused (e.g. as a possible zip bomb or an unsafe
+    /// path) or was skipped as a duplicate rather than filtered out, so
+    /// `delete_source` keeps the archive.
+    pub keep_source: bool,
+}
+
+impl Summary {
+    /// Adds the totals of `other` (one archive's tally) to these.
+    fn add(&mut self, other: &Summary) {
+        self.archives_processed += other.archives_processed;
+        self.archives_failed += other.archives_failed;
+        self.files_extracted += other.files_extracted;
+        self.files_failed += other.files_failed;
+        self.files_skipped += other.files_skipped;
+        self.run_duplicates += other.run_duplicates;
+        self.bytes_written += other.bytes_written;
+        self.files_new += other.files_new;
+        self.files_changed += other.files_changed;
+        self.files_identical += other.files_identical;
+        self.keep_source |= other.keep_source;
+    }
+}
+
+/// What [`undo_extraction`] did.
+#[derive(Default)]
+pub struct UndoSummary {
+    pub files_removed: usize,
+    pub folders_removed: usize,
+    /// Files left in place because they replaced an existing file or have
+    /// changed since they were written.
+    pub files_kept: usize,
+    /// Files and folders that could not be removed, with the reason.
+    pub errors: Vec<String>,
+}
+
+/// Deletes the files a run wrote, as reported by [`Message::Extracted`], along
+/// with the folders it created for them once they are empty. Files that
+/// overwrote an existing one are kept, as are files whose size has changed
+/// since, so nothing that was there before the run is lost.
+pub fn undo_extraction(files: &[ExtractedFile]) -> UndoSummary {
+    let mut summary = UndoSummary::default();
+    let mut folders = Vec::new();
+    for file in files {
+        folders.extend(file.created_dirs.iter().cloned());
+        let unchanged = fs::symlink_metadata(&file.path)
+            .is_ok_and(|metadata| !metadata.is_dir() && metadata.len() == file.size);
+        if file.replaced || !unchanged {
+            summary.files_kept += 1;
+            continue;
+        }
+        match fs::remove_file(&file.path) {
+            Ok(()) => summary.files_removed += 1,
+            Err(err) => summary
+                .errors
+                .push(format!("{}: {}", file.path.display(), err)),
+        }
+    }
+    // Deepest first, so a folder is emptied of its subfolders before its turn.
+    folders.sort_by(|a, b| {
+        let depth = |dir: &PathBuf| dir.components().count();
+        depth(b).cmp(&depth(a)).then_with(|| a.cmp(b))
+    });
+    folders.dedup();
+    for dir in folders {
+        let is_empty = fs::read_dir(&dir).is_ok_and(|mut entries| entries.next().is_none());
+        if is_empty && fs::remove_dir(&dir).is_ok() {
+            summary.folders_removed += 1;
+        }
+    }
+    summary
+}
+
+/// Free space in bytes on the drive that holds `path`, or `None` if it can't be
+/// determined. `path` doesn't have to exist yet; its nearest existing parent is
+/// checked instead.
+pub fn available_space(path: &Path) -> Option<u64> {
+    path.ancestors()
+        .map(|dir| {
+            if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            }
+        })
+        .find(|dir| dir.exists())
+        .and_then(|dir| fs2::available_space(dir).ok())
+}
+
+/// Formats a byte count for display, e.g. `1536` becomes `1.5 KB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Everything the per-archive functions need during one extraction run.
+struct ExtractContext<'a> {
+    options: &'a ExtractOptions,
+    /// Extensions from the filter (lowercased unless matching is case-sensitive);
+    /// empty means every file.
+    filter_exts: &'a [String],
+    /// Lowercased types wanted by the content filter; empty means every type.
+    content_types: &'a [String],
+    /// Where entries are written; the archive's own subfolder with `output_per_zip`.
+    output_dir: PathBuf,
+    /// The archive being processed, as shown in the log.
+    current_archive: PathBuf,
+    cancel_flag: &'a AtomicBool,
+    pause: &'a PauseControl,
+    report: &'a (dyn Fn(Message) + Sync),
+    progress: &'a Progress,
+    summary: Summary,
+    /// Size bounds in bytes from the min/max size fields; `None` means unbounded.
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    /// Compiled name pattern; entries must match it in addition to the extension filter.
+    name_filter: Option<&'a NameFilter>,
+    /// Fragments from `exclude_paths`, with '/' as the separator.
+    exclude_paths: &'a [String],
+    /// Parsed `name_template`, if one is set.
+    name_template: Option<&'a NameTemplate>,
+    /// Parsed `extension_folders`, with `use_extension_folders`.
+    extension_folders: Option<&'a ExtensionFolders>,
+    /// Entries of this archive that got as far as being named, for `{index}`.
+    entry_index: usize,
+    /// The output paths claimed so far in the run, across all archives. Held
+    /// while picking an output path and creating the file there, so two workers
+    /// can't both claim the same free name.
+    claim_lock: &'a Mutex<HashSet<PathBuf>>,
+    /// The answer to use for every collision, once one was given with "apply to all".
+    conflict_answer: &'a Mutex<Option<DuplicatePolicy>>,
+    /// Files extracted so far, for `write_manifest`.
+    manifest: &'a Mutex<Vec<ExtractedFile>>,
+    /// Files extracted so far per extension, for `max_per_extension`.
+    per_extension: &'a Mutex<HashMap<String, usize>>,
+    /// The zip being written when `output_zip` is set (and this isn't a dry run).
+    zip_output: Option<&'a Mutex<ZipOutput>>,
+    /// Encoding chosen for zip entry names; `None` to auto-detect.
+    name_encoding: Option<&'static Encoding>,
+    /// The output folder or zip, whose drive is watched with `pause_below_free_mb`.
+    output_target: &'a Path,
+}
+
+impl ExtractContext<'_> {
+    /// Blocks while the run is paused, after pausing it first if the output
+    /// drive is low on space.
+    fn wait_while_paused(&self) {
+        self.wait_for_free_space();
+        self.pause.wait_while_paused(self.cancel_flag);
+    }
+
+    /// With `pause_below_free_mb`, pauses the run while free space on the output
+    /// drive is below it. The run resumes by itself once space is freed. If the
+    /// user resumes it first, the run goes on and isn't paused for space again
+    /// until free space has been back above the threshold.
+    fn wait_for_free_space(&self) {
+        let threshold = self.options.pause_below_free_mb.saturating_mul(1024 * 1024);
+        if threshold == 0 || self.options.dry_run {
+            return;
+        }
+        let is_low = || available_space(self.output_target).is_some_and(|free| free < threshold);
+        let overridden = &self.pause.low_space_overridden;
+        if !is_low() {
+            overridden.store(false, Ordering::Relaxed);
+            return;
+        }
+        if overridden.load(Ordering::Relaxed) || self.pause.is_paused() {
+            return;
+        }
+        self.pause.pause();
+        let free = available_space(self.output_target).unwrap_or(0);
+        (self.report)(Message::Warning(format!(
+            "Only {} is free on the output drive, less than the {} to pause at. Paused \
+             until more space is free; resume to continue anyway.",
+            format_bytes(free),
+            format_bytes(threshold)
+        )));
+        while !self.cancel_flag.load(Ordering::Relaxed) && self.pause.is_paused() {
+            thread::sleep(LOW_SPACE_POLL_INTERVAL);
+            if !is_low() {
+                self.pause.resume();
+                (self.report)(Message::Log(
+                    "Enough space is free on the output drive again; resuming.\n".to_string(),
+                ));
+                return;
+            }
+        }
+        if !self.cancel_flag.load(Ordering::Relaxed) {
+            overridden.store(true, Ordering::Relaxed);
+            (self.report)(Message::Log(
+                "Resumed while the output drive is low on space; not pausing for it again \
+                 until space is freed.\n"
+                    .to_string(),
+            ));
+        }
+    }
+
+    /// Reports a warning or error about a single entry; hidden at
+    /// [`Verbosity::Quiet`], though it still counts in the summary.
+    fn report_entry(&self, message: Message) {
+        if self.options.verbosity != Verbosity::Quiet {
+            (self.report)(message);
+        }
+    }
+
+    /// Logs a line about a single entry; only shown at [`Verbosity::Verbose`].
+    fn log_entry(&self, text: String) {
+        if self.options.verbosity == Verbosity::Verbose {
+            (self.report)(Message::Log(text));
+        }
+    }
+
+    /// Whether the entry's path contains one of the `exclude_paths` fragments.
+    fn is_excluded(&self, entry_name: &str) -> bool {
+        let name = entry_name.replace('\\', "/");
+        self.exclude_paths
+            .iter()
+            .any(|fragment| name.contains(fragment.as_str()))
+    }
+
+    /// Whether the entry falls under one of the excluded paths; counts and logs it
+    /// as skipped if so.
+    fn skip_excluded(&mut self, entry_name: &str) -> bool {
+        if !self.is_excluded(entry_name) {
+            return false;
+        }
+        self.summary.files_skipped += 1;
+        self.log_entry(format!("Skipped (excluded path): {}\n", entry_name));
+        true
+    }
+
+    /// The policy for collisions if it's known without asking: the configured
+    /// one, or with [`DuplicatePolicy::Ask`] an answer given with "apply to all".
+    fn settled_duplicate_policy(&self) -> Option<DuplicatePolicy> {
+        if self.options.duplicate_policy != DuplicatePolicy::Ask {
+            return Some(self.options.duplicate_policy);
+        }
+        *self
+            .conflict_answer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// How to handle an entry that would land on the existing `path`: the
+    /// configured policy, or with [`DuplicatePolicy::Ask`] the user's answer.
+    /// Never returns `Ask`. May block until the user answers, so callers must
+    /// not hold `claim_lock` or the output zip while calling it.
+    fn duplicate_policy_for(&self, path: &Path) -> DuplicatePolicy {
+        if self.options.duplicate_policy != DuplicatePolicy::Ask {
+            return self.options.duplicate_policy;
+        }
+        let mut answer = self
+            .conflict_answer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(policy) = *answer {
+            return policy;
+        }
+        let (reply, choice) = mpsc::channel();
+        (self.report)(Message::Conflict {
+            path: path.to_path_buf(),
+            reply,
+        });
+        let Ok(choice) = choice.recv() else {
+            return DuplicatePolicy::Skip;
+        };
+        let policy = match choice.policy {
+            DuplicatePolicy::Ask => DuplicatePolicy::Skip,
+            policy => policy,
+        };
+        if choice.apply_to_all {
+            *answer = Some(policy);
+        }
+        policy
+    }
+}
+
+/// A compiled name pattern from the "Name pattern" field.
+enum NameFilter {
+    /// Glob matched against the file name only.
+    Glob(GlobMatcher),
+    /// Regex matched against the full entry name, folders included.
+    Regex(Regex),
+}
+
+impl NameFilter {
+    fn matches(&self, entry_name: &str) -> bool {
+        match self {
+            NameFilter::Glob(glob) => Path::new(entry_name)
+                .file_name()
+                .is_some_and(|file_name| glob.is_match(file_name)),
+            NameFilter::Regex(regex) => regex.is_match(entry_name),
+        }
+    }
+}
+
+/// The parsed `extension_folders` table: which folder, relative to the output
+/// directory, the files with each (lowercased) extension go into.
+struct ExtensionFolders {
+    by_extension: HashMap<String, PathBuf>,
+    /// For extensions that aren't listed, and files without one. `None` when
+    /// those files are skipped.
+    default: Option<PathBuf>,
+}
+
+impl ExtensionFolders {
+    fn parse(rows: &[ExtensionFolder], default: &str, skip_others: bool) -> Result<Self, String> {
+        let folder_path = |folder: &str| {
+            let folder = folder.trim();
+            let climbs_out = folder.starts_with(['/', '\\'])
+                || folder.split(['/', '\\']).any(|part| part == "..");
+            if climbs_out {
+                return Err(format!(
+                    "Extension folder \"{}\" has to stay inside the output directory",
+                    folder
+                ));
+            }
+            Ok(folder
+                .split(['/', '\\'])
+                .filter(|part| !part.is_empty() && *part != ".")
+                .map(sanitize_filename)
+                .collect::<PathBuf>())
+        };
+        let mut by_extension = HashMap::new();
+        for row in rows {
+            let folder = folder_path(&row.folder)?;
+            let extensions = row
+                .extensions
+                .split(',')
+                .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+                .filter(|ext| !ext.is_empty());
+            for ext in extensions {
+                if by_extension.insert(ext.clone(), folder.clone()).is_some() {
+                    return Err(format!(
+                        "Extension {} is mapped to more than one folder",
+                        ext
+                    ));
+                }
+            }
+        }
+        let default = if skip_others {
+            None
+        } else {
+            Some(folder_path(default)?)
+        };
+        Ok(ExtensionFolders {
+            by_extension,
+            default,
+        })
+    }
+
+    /// The folder for a file named `file_name`, or `None` if it is in no group
+    /// and such files are skipped.
+    fn folder_for(&self, file_name: &Path) -> Option<&Path> {
+        file_name
+            .extension()
+            .and_then(|ext| self.by_extension.get(&ext.to_string_lossy().to_lowercase()))
+            .or(self.default.as_ref())
+            .map(PathBuf::as_path)
+    }
+
+    /// E.g. "jpg, png -> Images; pdf -> Documents; others -> Other", for the log.
+    fn describe(&self) -> String {
+        let mut folders: Vec<(&PathBuf, Vec<&str>)> = Vec::new();
+        for (ext, folder) in &self.by_extension {
+            match folders.iter_mut().find(|(existing, _)| *existing == folder) {
+                Some((_, exts)) => exts.push(ext),
+                None => folders.push((folder, vec![ext])),
+            }
+        }
+        folders.sort();
+        let mut parts: Vec<String> = folders
+            .into_iter()
+            .map(|(folder, mut exts)| {
+                exts.sort();
+                format!("{} -> {}", exts.join(", "), shown_folder(folder))
+            })
+            .collect();
+        parts.push(match &self.default {
+            Some(folder) => format!("others -> {}", shown_folder(folder)),
+            None => "others skipped".to_string(),
+        });
+        parts.join("; ")
+    }
+}
+
+/// A folder relative to the output directory as shown in the log, with "."
+/// for the output directory itself.
+fn shown_folder(folder: &Path) -> String {
+    if folder.as_os_str().is_empty() {
+        ".".to_string()
+    } else {
+        folder.display().to_string()
+    }
+}
+
+/// A parsed `name_template`. Placeholders:
+/// - `{name}`: the entry's file name, e.g. "report.pdf"
+/// - `{stem}` and `{ext}`: the file name without and only its extension ("report", "pdf")
+/// - `{zipstem}`: the archive's name without its extension
+/// - `{index}`: 1 for the first file named in each archive, 2 for the next, ...
+/// - `{date}`: the entry's modified date as YYYY-MM-DD, or "undated"
+///
+/// A `/` in the template makes a subfolder.
+struct NameTemplate {
+    parts: Vec<TemplatePart>,
+}
+
+enum TemplatePart {
+    Text(String),
+    Name,
+    Stem,
+    Ext,
+    ZipStem,
+    Index,
+    Date,
+}
+
+impl NameTemplate {
+    fn parse(template: &str) -> Result<Self, String> {
+        let climbs_out = template.starts_with(['/', '\\'])
+            || template.split(['/', '\\']).any(|part| part == "..");
+        if climbs_out {
+            return Err("it has to stay inside the output directory".to_string());
+        }
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(TemplatePart::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..].find('}').ok_or("a '{' is never closed")? + start;
+            parts.push(match &rest[start + 1..end] {
+                "name" => TemplatePart::Name,
+                "stem" => TemplatePart::Stem,
+                "ext" => TemplatePart::Ext,
+                "zipstem" => TemplatePart::ZipStem,
+                "index" => TemplatePart::Index,
+                "date" => TemplatePart::Date,
+                other => return Err(format!("unknown placeholder {{{}}}", other)),
+            });
+            rest = &rest[end + 1..];
+        }
+        if rest.contains('}') {
+            return Err("a '}' has no matching '{'".to_string());
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Text(rest.to_string()));
+        }
+        Ok(NameTemplate { parts })
+    }
+
+    /// The relative output path for an entry whose file name is `file_name`.
+    /// `None` if the result is empty or would climb out of the output directory.
+    fn expand(
+        &self,
+        file_name: &Path,
+        entry: &EntryInfo,
+        archive: &Path,
+        index: usize,
+    ) -> Option<PathBuf> {
+        let mut expanded = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(text) => expanded.push_str(text),
+                TemplatePart::Name => expanded.push_str(&file_name.to_string_lossy()),
+                TemplatePart::Stem => {
+                    if let Some(stem) = file_name.file_stem() {
+                        expanded.push_str(&stem.to_string_lossy());
+                    }
+                }
+                TemplatePart::Ext => {
+                    if let Some(ext) = file_name.extension() {
+                        expanded.push_str(&ext.to_string_lossy());
+                    }
+                }
+                TemplatePart::ZipStem => expanded.push_str(&archive_stem(archive)),
+                TemplatePart::Index => expanded.push_str(&index.to_string()),
+                TemplatePart::Date => match entry.modified {
+                    Some(modified) => expanded.push_str(&modified.format("%Y-%m-%d").to_string()),
+                    None => expanded.push_str("undated"),
+                },
+            }
+        }
+        let path = sanitize_entry_path(&expanded).ok()?;
+        Some(
+            path.iter()
+                .map(|part| sanitize_filename(&part.to_string_lossy()))
+                .collect(),
+        )
+    }
+}
+
+/// Parses a size field given in KB into bytes. An empty field means no bound.
+fn parse_size_kb(text: &str) -> Result<Option<u64>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    text.parse::<u64>()
+        .map(|kb| Some(kb.saturating_mul(1024)))
+        .map_err(|_| format!("\"{}\" is not a whole number of KB", text))
+}
+
+/// Archive formats the extractor can read, detected by file extension.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    /// Gzip-compressed tar (`.tar.gz` or `.tgz`).
+    TarGz,
+    /// 7-Zip (`.7z`).
+    SevenZ,
+}
+
+impl ArchiveKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".7z") {
+            Some(ArchiveKind::SevenZ)
+        } else {
+            None
+        }
+    }
+}
+
+/// Replaces characters that are not allowed in file names on common file systems
+/// (including Windows) with '_'.
+fn filesystem_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// The archive's file name without its archive extension, e.g. "photos" for
+/// "photos.tar.gz". Used to name its subfolder when `output_per_zip` is set.
+fn archive_stem(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let lower = name.to_lowercase();
+    let suffix_len = [".tar.gz", ".tgz", ".tar", ".zip", ".7z"]
+        .iter()
+        .find(|suffix| lower.ends_with(*suffix))
+        .map_or(0, |suffix| suffix.len());
+    let stem = &name[..name.len() - suffix_len];
+    if stem.is_empty() {
+        "archive".to_string()
+    } else {
+        stem.to_string()
+    }
+}
+
+/// An archive queued for processing in a run.
+struct ArchiveInput {
+    path: PathBuf,
+    /// Path shown in the log; relative to the input folder it was found in.
+    shown_path: PathBuf,
+    kind: ArchiveKind,
+}
+
+/// The input that reads an archive from standard input, e.g. for piping one into
+/// the command-line mode.
+const STDIN_INPUT: &str = "-";
+
+/// Whether an input should be downloaded rather than read from disk.
+fn is_url(input: &str) -> bool {
+    let lower = input.to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Whether an input is a glob pattern such as `C:\data\*.zip` rather than a
+/// path. An existing path is taken literally even if its name contains `[`.
+fn is_input_pattern(input: &str) -> bool {
+    input.contains(['*', '?', '[']) && !Path::new(input).exists()
+}
+
+/// The archives matched by a glob pattern input. Matches that aren't archives
+/// (folders included) are left out.
+fn expand_input_pattern(
+    pattern: &str,
+    report: &dyn Fn(Message),
+) -> Result<Vec<ArchiveInput>, Box<dyn Error>> {
+    let matches = glob::glob(pattern)
+        .map_err(|err| format!("Invalid input pattern \"{}\": {}", pattern, err))?;
+    let mut matched = 0;
+    let mut archives = Vec::new();
+    for path in matches {
+        let path = path?;
+        matched += 1;
+        if let Some(kind) = ArchiveKind::from_path(&path).filter(|_| path.is_file()) {
+            archives.push(ArchiveInput {
+                shown_path: path.clone(),
+                path,
+                kind,
+            });
+        }
+    }
+    report(Message::Log(format!(
+        "Pattern {} matched {} path(s), {} of them archives.\n",
+        pattern,
+        matched,
+        archives.len()
+    )));
+    Ok(archives)
+}
+
+/// An archive downloaded from a URL (or read from standard input) into a temp
+/// file, which is deleted again on drop.
+struct TempDownload {
+    path: PathBuf,
+    kind: ArchiveKind,
+}
+
+impl TempDownload {
+    /// Downloads `source` into a new temp file, reporting progress as it goes.
+    /// `index` keeps the files of one run apart. The archive type is taken from
+    /// the end of the URL's path; anything else, including standard input, is
+    /// read as a zip.
+    fn fetch(
+        source: &str,
+        index: usize,
+        options: &ExtractOptions,
+        cancel_flag: &AtomicBool,
+        report: &dyn Fn(Message),
+    ) -> Result<Self, Box<dyn Error>> {
+        let (mut reader, total): (Box<dyn Read>, u64) = if source == STDIN_INPUT {
+            report(Message::Log(
+                "Reading archive from standard input.\n".to_string(),
+            ));
+            (Box::new(io::stdin().lock()), 0)
+        } else {
+            report(Message::Log(format!("Downloading: {}\n", source)));
+            let response = ureq::get(source)
+                .call()
+                .map_err(|err| format!("Download failed: {}", err))?;
+            let total = response
+                .header("Content-Length")
+                .and_then(|length| length.parse().ok())
+                .unwrap_or(0);
+            (response.into_reader(), total)
+        };
+        let url_path = source.split(['?', '#']).next().unwrap_or(source);
+        let kind = ArchiveKind::from_path(Path::new(url_path)).unwrap_or(ArchiveKind::Zip);
+        let suffix = match kind {
+            ArchiveKind::Zip => "zip",
+            ArchiveKind::Tar => "tar",
+            ArchiveKind::TarGz => "tar.gz",
+            ArchiveKind::SevenZ => "7z",
+        };
+        let (path, file) = create_temp_file(index, suffix)?;
+        // Created before writing, so a failed download is cleaned up as well.
+        let download = TempDownload { path, kind };
+        let mut file = BufWriter::new(file);
+        let mut buffer = vec![0; options.buffer_size()];
+        let mut written = 0;
+        loop {
+            if cancel_flag.load(Ordering::Relaxed) {
+                return Err("Download cancelled by user.".into());
+            }
+            let read = reader
+                .read(&mut buffer)
+                .map_err(|err| format!("Could not download {}: {}", source, err))?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])?;
+            written += read as u64;
+            report(Message::FileProgress {
+                name: source.to_string(),
+                written,
+                total,
+            });
+        }
+        file.flush()?;
+        report(Message::Log(format!(
+            "Downloaded {} ({})\n",
+            source,
+            format_bytes(written)
+        )));
+        Ok(download)
+    }
+}
+
+impl Drop for TempDownload {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Creates a new file in the temp folder with a random part in its name, for a
+/// download. The file must not exist yet, so a file or symlink put in place by
+/// someone else in a shared temp folder is never written through; another
+/// name is tried instead.
+fn create_temp_file(index: usize, suffix: &str) -> io::Result<(PathBuf, File)> {
+    const ATTEMPTS: usize = 16;
+    for _ in 0..ATTEMPTS {
+        // Each `RandomState` is seeded with fresh random keys.
+        let random = RandomState::new().build_hasher().finish();
+        let path = std::env::temp_dir().join(format!(
+            "zip_file_extractor_{}_{}_{:016x}.{}",
+            std::process::id(),
+            index,
+            random,
+            suffix
+        ));
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "could not find an unused temp file name",
+    ))
+}
+
+/// With `test_integrity`, reads every entry of every archive to the end and
+/// reports which archives are intact, without writing anything. Reading a zip
+/// entry to the end makes the reader check its CRC32; 7z entries and gzip
+/// streams are checked the same way, tar headers by their checksums.
+fn test_archives(
+    options: &ExtractOptions,
+    cancel_flag: &AtomicBool,
+    pause: &PauseControl,
+    report: &dyn Fn(Message),
+) -> Result<Summary, Box<dyn Error>> {
+    report(Message::Log(
+        "Testing archive integrity: no files will be written.\n".to_string(),
+    ));
+    let (archives, _downloads) = collect_inputs(options, cancel_flag, report)?;
+    let total = archives
+        .iter()
+        .filter_map(|archive| scan_archive(&archive.path, archive.kind, &|_| false))
+        .map(|scan| scan.entries)
+        .sum();
+    let progress = Progress {
+        done: AtomicUsize::new(0),
+        total,
+        bytes_written: AtomicU64::new(0),
+        bytes_total: 0,
+    };
+    report(progress.message());
+
+    let mut summary = Summary::default();
+    let mut results = Vec::new();
+    for archive in &archives {
+        let tested = test_archive(archive, options, &progress, cancel_flag, pause, report);
+        if cancel_flag.load(Ordering::Relaxed) {
+            report(Message::Log("Test cancelled by user.\n".to_string()));
+            return Ok(summary);
+        }
+        summary.archives_processed += 1;
+        let shown = archive.shown_path.display();
+        match tested {
+            Ok(entries) => {
+                report(Message::Log(format!(
+                    "OK: {} ({} entries)\n",
+                    shown, entries
+                )));
+                results.push(format!("OK      {}", shown));
+            }
+            Err(err) => {
+                report(Message::Error(format!("FAILED: {}: {}", shown, err)));
+                summary.archives_failed += 1;
+                results.push(format!("FAILED  {}", shown));
+            }
+        }
+    }
+    report(Message::Log(format!(
+        "Integrity test: {} archive(s) OK, {} FAILED.\n",
+        summary.archives_processed - summary.archives_failed,
+        summary.archives_failed
+    )));
+    for line in results {
+        report(Message::Log(format!("  {}\n", line)));
+    }
+    Ok(summary)
+}
+
+/// Reads through every entry of one archive, returning how many there were or
+/// the first error, prefixed with the entry it happened in. Returns early, with
+/// what was read so far, once the run is cancelled.
+fn test_archive(
+    archive: &ArchiveInput,
+    options: &ExtractOptions,
+    progress: &Progress,
+    cancel_flag: &AtomicBool,
+    pause: &PauseControl,
+    report: &dyn Fn(Message),
+) -> Result<usize, Box<dyn Error>> {
+    report(Message::Log(format!(
+        "Testing: {}\n",
+        archive.shown_path.display()
+    )));
+    // Called before each entry; `false` means stop.
+    let next_entry = || {
+        pause.wait_while_paused(cancel_flag);
+        if cancel_flag.load(Ordering::Relaxed) {
+            return false;
+        }
+        report(progress.advance());
+        true
+    };
+    let in_entry = |name: &str, err: &dyn Error| format!("{}: {}", name, err);
+    let mut entries = 0;
+    match archive.kind {
+        ArchiveKind::Zip => {
+            let file = BufReader::with_capacity(options.buffer_size(), File::open(&archive.path)?);
+            let mut zip = ZipArchive::new(file)?;
+            for i in 0..zip.len() {
+                if !next_entry() {
+                    break;
+                }
+                let mut entry = if options.password.is_empty() {
+                    zip.by_index(i)?
+                } else {
+                    zip.by_index_decrypt(i, options.password.as_bytes())?
+                };
+                let name = entry.name().to_string();
+                io::copy(&mut entry, &mut io::sink()).map_err(|err| in_entry(&name, &err))?;
+                entries += 1;
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let gzipped = archive.kind == ArchiveKind::TarGz;
+            let mut tar = open_tar(&archive.path, gzipped, options.buffer_size())?;
+            for entry in tar.entries()? {
+                if !next_entry() {
+                    return Ok(entries);
+                }
+                let mut entry = entry?;
+                let name = entry.path()?.to_string_lossy().into_owned();
+                io::copy(&mut entry, &mut io::sink()).map_err(|err| in_entry(&name, &err))?;
+                entries += 1;
+            }
+            // The gzip trailer with the CRC comes after the end of the tar data.
+            io::copy(&mut tar.into_inner(), &mut io::sink())?;
+        }
+        ArchiveKind::SevenZ => {
+            let password = Password::from(options.password.as_str());
+            let mut sevenz = SevenZReader::open(&archive.path, password)?;
+            let mut current = String::new();
+            sevenz
+                .for_each_entries(|entry, reader| {
+                    if !next_entry() {
+                        return Ok(false);
+                    }
+                    current = entry.name().to_string();
+                    io::copy(reader, &mut io::sink())?;
+                    entries += 1;
+                    Ok(true)
+                })
+                .map_err(|err| in_entry(&current, &err))?;
+        }
+    }
+    Ok(entries)
+}
+
+/// Resolves every non-blank row of the input list into the archives it stands
+/// for, downloading URLs and standard input into temp files, which are deleted
+/// once the returned downloads are dropped.
+fn collect_inputs(
+    options: &ExtractOptions,
+    cancel_flag: &AtomicBool,
+    report: &dyn Fn(Message),
+) -> Result<(Vec<ArchiveInput>, Vec<TempDownload>), Box<dyn Error>> {
+    let inputs: Vec<String> = options
+        .input_paths
+        .iter()
+        .map(|input| input.trim())
+        .filter(|input| !input.is_empty())
+        .map(|input| {
+            let expanded = expand_env_vars(input);
+            if expanded != input {
+                report(Message::Log(format!("Input resolved to: {}\n", expanded)));
+            }
+            expanded
+        })
+        .collect();
+    if inputs.is_empty() {
+        return Err("No input paths given.".into());
+    }
+
+    let mut downloads = Vec::new();
+    let mut archives = Vec::new();
+    for input in &inputs {
+        let input = input.as_str();
+        if is_url(input) || input == STDIN_INPUT {
+            let download =
+                TempDownload::fetch(input, downloads.len(), options, cancel_flag, report)?;
+            archives.push(ArchiveInput {
+                path: download.path.clone(),
+                shown_path: PathBuf::from(input),
+                kind: download.kind,
+            });
+            downloads.push(download);
+        } else if is_input_pattern(input) {
+            archives.extend(expand_input_pattern(input, report)?);
+        } else {
+            archives.extend(collect_archives(
+                Path::new(input),
+                options.recursive,
+                report,
+            )?);
+        }
+    }
+    report(Message::Log(format!(
+        "Found {} archive(s) to process.\n",
+        archives.len()
+    )));
+    Ok((archives, downloads))
+}
+
+/// Resolves one input into the archives it stands for. Archives found inside a
+/// directory are shown relative to it. A file given directly is read as a zip
+/// unless its extension says it is a tar archive.
+fn collect_archives(
+    input_path: &Path,
+    recursive: bool,
+    report: &dyn Fn(Message),
+) -> Result<Vec<ArchiveInput>, Box<dyn Error>> {
+    if input_path.is_dir() {
+        report(Message::Log(format!(
+            "Scanning folder: {}\n",
+            input_path.display()
+        )));
+        let archives = find_archives(input_path, recursive, report)?
+            .into_iter()
+            .map(|(path, kind)| ArchiveInput {
+                shown_path: path.strip_prefix(input_path).unwrap_or(&path).to_path_buf(),
+                path,
+                kind,
+            })
+            .collect();
+        Ok(archives)
+    } else if input_path.is_file() {
+        Ok(vec![ArchiveInput {
+            path: input_path.to_path_buf(),
+            shown_path: input_path.to_path_buf(),
+            kind: ArchiveKind::from_path(input_path).unwrap_or(ArchiveKind::Zip),
+        }])
+    } else {
+        Err(format!("{} is not a valid file or directory.", input_path.display()).into())
+    }
+}
+
+/// What the up-front scan of an archive found.
+struct ArchiveScan {
+    entries: usize,
+    /// Uncompressed size of the entries whose names pass `counts_toward_bytes`.
+    matching_bytes: u64,
+    /// Sizes of those entries with and without compression. Only known for zips,
+    /// which compress each entry on its own.
+    compression: Option<CompressionStats>,
+}
+
+/// Compressed and uncompressed size of a set of zip entries.
+#[derive(Default)]
+struct CompressionStats {
+    uncompressed: u64,
+    compressed: u64,
+}
+
+impl CompressionStats {
+    fn add(&mut self, other: &CompressionStats) {
+        self.uncompressed += other.uncompressed;
+        self.compressed += other.compressed;
+    }
+
+    /// E.g. "Uncompressed 1.2 GB from 400.0 MB (3.0x, 800.0 MB saved)", or `None`
+    /// when there is nothing to compare.
+    fn describe(&self) -> Option<String> {
+        if self.compressed == 0 {
+            return None;
+        }
+        Some(format!(
+            "Uncompressed {} from {} ({:.1}x, {} saved)",
+            format_bytes(self.uncompressed),
+            format_bytes(self.compressed),
+            self.uncompressed as f64 / self.compressed as f64,
+            format_bytes(self.uncompressed.saturating_sub(self.compressed))
+        ))
+    }
+}
+
+/// Counts the entries of an archive and the bytes that are expected to be
+/// written from it, or `None` if it can't be read. For zips only the central
+/// directory is read, so this is cheap. Tar archives have no central directory,
+/// so this reads through the whole file (decompressing `.tar.gz`). For 7z only
+/// the header is read.
+fn scan_archive(
+    path: &Path,
+    kind: ArchiveKind,
+    counts_toward_bytes: &dyn Fn(&str) -> bool,
+) -> Option<ArchiveScan> {
+    let mut scan = ArchiveScan {
+        entries: 0,
+        matching_bytes: 0,
+        compression: None,
+    };
+    match kind {
+        ArchiveKind::Zip => {
+            let file = File::open(path).ok()?;
+            let mut archive = ZipArchive::new(file).ok()?;
+            scan.entries = archive.len();
+            let mut stats = CompressionStats::default();
+            for i in 0..archive.len() {
+                // Raw access reads only the headers, and needs no password.
+                let Ok(entry) = archive.by_index_raw(i) else {
+                    continue;
+                };
+                if entry.is_file() && counts_toward_bytes(entry.name()) {
+                    scan.matching_bytes += entry.size();
+                    stats.uncompressed += entry.size();
+                    stats.compressed += entry.compressed_size();
+                }
+            }
+            scan.compression = Some(stats);
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let mut archive = open_tar(path, kind == ArchiveKind::TarGz, COPY_CHUNK_SIZE).ok()?;
+            for entry in archive.entries().ok()?.flatten() {
+                scan.entries += 1;
+                let matches = entry.header().entry_type().is_file()
+                    && entry
+                        .path()
+                        .is_ok_and(|name| counts_toward_bytes(&name.to_string_lossy()));
+                if matches {
+                    scan.matching_bytes += entry.size();
+                }
+            }
+        }
+        // Only the header is read. Archives with an encrypted header can't be
+        // scanned without the password and are left out.
+        ArchiveKind::SevenZ => {
+            let archive = sevenz_rust::Archive::open(path).ok()?;
+            scan.entries = archive.files.len();
+            scan.matching_bytes = archive
+                .files
+                .iter()
+                .filter(|entry| !entry.is_directory() && counts_toward_bytes(entry.name()))
+                .map(|entry| entry.size())
+                .sum();
+        }
+    }
+    Some(scan)
+}
+
+/// Collects the archives inside `dir`. With `recursive` set, subdirectories
+/// are walked as well; each directory is visited at most once (by canonical
+/// path) so symlink loops can't cause endless recursion. Each archive is
+/// reported as it is found, so a slow walk over a network share shows progress.
+fn find_archives(
+    dir: &Path,
+    recursive: bool,
+    report: &dyn Fn(Message),
+) -> io::Result<Vec<(PathBuf, ArchiveKind)>> {
+    let mut archives = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        if !visited.insert(fs::canonicalize(&current)?) {
+            continue;
+        }
+        for entry in fs::read_dir(&current)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.is_dir() {
+                if recursive {
+                    pending.push(path);
+                }
+            } else if path.is_file() {
+                if let Some(kind) = ArchiveKind::from_path(&path) {
+                    let label = match kind {
+                        ArchiveKind::Zip => "zip",
+                        ArchiveKind::Tar | ArchiveKind::TarGz => "tar",
+                        ArchiveKind::SevenZ => "7z",
+                    };
+                    report(Message::Log(format!(
+                        "Found {}: {}\n",
+                        label,
+                        path.display()
+                    )));
+                    archives.push((path, kind));
+                }
+            }
+        }
+    }
+    Ok(archives)
+}
+
+fn has_zip_extension(path: &Path) -> bool {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.eq_ignore_ascii_case("zip"))
+        .unwrap_or(false)
+}
+
+/// Opens the zip file at `zip_path` and extracts its entries.
+fn process_zip_file_thread(
+    zip_path: &Path,
+    ctx: &mut ExtractContext,
+) -> Result<(), Box<dyn Error>> {
+    let file = BufReader::with_capacity(ctx.options.buffer_size(), File::open(zip_path)?);
+    let mut archive = ZipArchive::new(file)?;
+    process_archive(&mut archive, 1, ctx)
+}
+
+/// Extracts the entries of an already opened archive.
+/// Files whose names include "__MACOSX" are skipped when `skip_macosx` is set;
+/// the rest go through
+/// `extract_entry`.
+/// With `extract_nested` set, `.zip` entries are opened in memory and processed the
+/// same way, up to `max_nested_depth` levels deep (the outermost archive is depth 1).
+/// Encrypted entries are decrypted with `password`; a missing or wrong password
+/// is logged and ends processing of this archive without failing the whole run.
+/// Returns early once the cancel flag is set.
+fn process_archive<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    depth: usize,
+    ctx: &mut ExtractContext,
+) -> Result<(), Box<dyn Error>> {
+    let options = ctx.options;
+    let report = ctx.report;
+    for i in 0..archive.len() {
+        ctx.wait_while_paused();
+        if ctx.cancel_flag.load(Ordering::Relaxed) {
+            report(Message::Log("Extraction cancelled by user.\n".to_string()));
+            return Ok(());
+        }
+
+        // Progress only tracks the outermost archives; their entry counts are
+        // what the total was built from.
+        if depth == 1 {
+            report(ctx.progress.advance());
+        }
+
+        // For the log if the entry can't be opened; the entry borrows the archive.
+        let failed_name = archive
+            .name_for_index(i)
+            .map_or_else(|| format!("entry {}", i + 1), str::to_string);
+        let entry = if options.password.is_empty() {
+            archive.by_index(i)
+        } else {
+            archive.by_index_decrypt(i, options.password.as_bytes())
+        };
+        let mut zip_file = match entry {
+            Ok(zip_file) => zip_file,
+            Err(ZipError::UnsupportedArchive(reason)) if reason == ZipError::PASSWORD_REQUIRED => {
+                report(Message::Error(
+                    "Archive is encrypted, password required.".to_string(),
+                ));
+                ctx.summary.archives_failed += 1;
+                return Ok(());
+            }
+            Err(ZipError::InvalidPassword) => {
+                report(Message::Error(
+                    "Wrong password for this archive, skipping it.".to_string(),
+                ));
+                ctx.summary.archives_failed += 1;
+                return Ok(());
+            }
+            // E.g. an unsupported compression method; the other entries may be fine.
+            Err(err) => {
+                entry_failed(&failed_name, &err, ctx);
+                continue;
+            }
+        };
+        let entry_name = decode_entry_name(
+            zip_file.name_raw(),
+            zip_file.name(),
+            zip_file.get_metadata().is_utf8,
+            ctx.name_encoding,
+        );
+
+        // Skip entries that are part of the "__MACOSX" metadata, unless asked not to.
+        if options.skip_macosx && entry_name.contains("__MACOSX") {
+            continue;
+        }
+
+        // Process only file and symlink entries.
+        let is_symlink = zip_file.is_symlink();
+        if zip_file.is_file() || is_symlink {
+            if ctx.skip_excluded(&entry_name) {
+                continue;
+            }
+            // Checked before nested zips are read into memory, too.
+            if let Some(ratio) =
+                excessive_ratio(zip_file.size(), zip_file.compressed_size(), options)
+            {
+                ctx.report_entry(Message::Warning(format!(
+                    "Refused possible zip bomb: {} expands {}x, more than the limit of {}x",
+                    entry_name, ratio, options.max_compression_ratio
+                )));
+                ctx.summary.files_skipped += 1;
+                ctx.summary.keep_source = true;
+                continue;
+            }
+            // Nested zips are opened in memory and extracted like the outer one,
+            // as long as the depth limit allows it.
+            if !is_symlink && options.extract_nested && has_zip_extension(Path::new(&entry_name)) {
+                if depth < options.max_nested_depth {
+                    report(Message::Log(format!(
+                        "Descending into nested zip: {} (depth {})\n",
+                        entry_name,
+                        depth + 1
+                    )));
+                    let mut buffer = Vec::new();
+                    let result = zip_file
+                        .read_to_end(&mut buffer)
+                        .map_err(Box::<dyn Error>::from)
+                        .and_then(|_| {
+                            let mut nested = ZipArchive::new(Cursor::new(buffer))?;
+                            process_archive(&mut nested, depth + 1, ctx)
+                        });
+                    if let Err(err) = result {
+                        entry_failed(&entry_name, err.as_ref(), ctx);
+                    }
+                    continue;
+                }
+                ctx.report_entry(Message::Warning(format!(
+                    "Nested zip depth limit ({}) reached, not descending into: {}",
+                    options.max_nested_depth, entry_name
+                )));
+            }
+
+            // Zips store a symlink's target as the entry's contents.
+            let link_target = if is_symlink {
+                let mut target = String::new();
+                if let Err(err) = zip_file.read_to_string(&mut target) {
+                    entry_failed(&entry_name, &err, ctx);
+                    continue;
+                }
+                Some(PathBuf::from(target))
+            } else {
+                None
+            };
+            let info = EntryInfo {
+                size: zip_file.size(),
+                modified: zip_file.last_modified().and_then(zip_datetime_to_naive),
+                crc32: Some(zip_file.crc32()),
+                name: entry_name,
+                link_target,
+            };
+            if let Err(err) = extract_entry(&mut zip_file, &info, ctx) {
+                entry_failed(&info.name, err.as_ref(), ctx);
+            }
+        } else if zip_file.is_dir() {
+            if let Err(err) = extract_directory(&entry_name, ctx) {
+                entry_failed(&entry_name, err.as_ref(), ctx);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Logs an entry that could not be extracted and counts it, so the rest of the
+/// archive can still be extracted. The archive is kept with `delete_source`.
+fn entry_failed(entry_name: &str, err: &dyn Error, ctx: &mut ExtractContext) {
+    ctx.report_entry(Message::Error(format!(
+        "Failed to extract {}: {}",
+        entry_name, err
+    )));
+    ctx.summary.files_failed += 1;
+    ctx.summary.keep_source = true;
+}
+
+/// The compression ratio of an entry, if it's above `max_compression_ratio`.
+fn excessive_ratio(size: u64, compressed_size: u64, options: &ExtractOptions) -> Option<u64> {
+    if options.max_compression_ratio == 0 {
+        return None;
+    }
+    let ratio = size / compressed_size.max(1);
+    (ratio > options.max_compression_ratio).then_some(ratio)
+}
+
+/// A file entry of a zip archive, as listed by [`list_zip_entries`].
+#[derive(Clone)]
+pub struct ZipEntryListing {
+    pub name: String,
+    pub size: u64,
+    pub compressed_size: u64,
+}
+
+impl ZipEntryListing {
+    /// Compressed size as a fraction of the original size; 1.0 for empty or stored files.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.size == 0 {
+            1.0
+        } else {
+            self.compressed_size as f64 / self.size as f64
+        }
+    }
+}
+
+/// Lists the file entries of a zip archive without extracting anything.
+/// Encrypted entries are listed too; their contents aren't read. Names are
+/// decoded as with the `name_encoding` option, so they match the names an
+/// extraction with the same setting sees.
+pub fn list_zip_entries(
+    zip_path: &Path,
+    name_encoding: &str,
+) -> Result<Vec<ZipEntryListing>, Box<dyn Error>> {
+    let encoding = parse_name_encoding(name_encoding)?;
+    let mut archive = ZipArchive::new(File::open(zip_path)?)?;
+    let mut entries = Vec::new();
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        if entry.is_file() {
+            entries.push(ZipEntryListing {
+                name: decode_entry_name(
+                    entry.name_raw(),
+                    entry.name(),
+                    entry.get_metadata().is_utf8,
+                    encoding,
+                ),
+                size: entry.size(),
+                compressed_size: entry.compressed_size(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// The encoding named by the `name_encoding` option; `None` when it is blank.
+fn parse_name_encoding(label: &str) -> Result<Option<&'static Encoding>, String> {
+    let label = label.trim();
+    if label.is_empty() {
+        return Ok(None);
+    }
+    Encoding::for_label(label.as_bytes())
+        .map(Some)
+        .ok_or_else(|| format!("Unknown entry name encoding \"{}\"", label))
+}
+
+/// Decodes the raw bytes of a zip entry name. Names flagged as UTF-8 (`is_utf8`)
+/// are taken as `decoded`, the zip crate's own reading. For the rest, with an
+/// `encoding` given, the bytes are decoded with it. Otherwise names that are
+/// valid UTF-8 are taken as such (many tools write UTF-8 without setting the
+/// flag that says so), and the rest fall back to `decoded`, read as CP437.
+fn decode_entry_name(
+    raw: &[u8],
+    decoded: &str,
+    is_utf8: bool,
+    encoding: Option<&'static Encoding>,
+) -> String {
+    if is_utf8 {
+        return decoded.to_string();
+    }
+    match encoding {
+        Some(encoding) => encoding.decode_without_bom_handling(raw).0.into_owned(),
+        None => match std::str::from_utf8(raw) {
+            Ok(name) => name.to_string(),
+            Err(_) => decoded.to_string(),
+        },
+    }
+}
+
+/// Opens the tar archive at `tar_path` (gzip-compressed when `gzipped` is set)
+/// and extracts its regular file entries the same way as zip entries, including
+/// the optional "__MACOSX" skipping. Returns early once the cancel flag is set.
+fn process_tar_file_thread(
+    tar_path: &Path,
+    gzipped: bool,
+    ctx: &mut ExtractContext,
+) -> Result<(), Box<dyn Error>> {
+    let options = ctx.options;
+    let report = ctx.report;
+    let mut archive = open_tar(tar_path, gzipped, options.buffer_size())?;
+    for entry in archive.entries()? {
+        ctx.wait_while_paused();
+        if ctx.cancel_flag.load(Ordering::Relaxed) {
+            report(Message::Log("Extraction cancelled by user.\n".to_string()));
+            return Ok(());
+        }
+
+        report(ctx.progress.advance());
+
+        let mut entry = entry?;
+        let entry_name = entry.path()?.to_string_lossy().into_owned();
+
+        // Skip entries that are part of the "__MACOSX" metadata, unless asked not to.
+        if options.skip_macosx && entry_name.contains("__MACOSX") {
+            continue;
+        }
+
+        // Process only regular file and symlink entries.
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_file() || entry_type.is_symlink() {
+            if ctx.skip_excluded(&entry_name) {
+                continue;
+            }
+            let link_target = if entry_type.is_symlink() {
+                entry.link_name()?.map(|target| target.into_owned())
+            } else {
+                None
+            };
+            let info = EntryInfo {
+                size: entry.size(),
+                modified: entry.header().mtime().ok().and_then(unix_time_to_naive),
+                crc32: None,
+                name: entry_name,
+                link_target,
+            };
+            if let Err(err) = extract_entry(&mut entry, &info, ctx) {
+                entry_failed(&info.name, err.as_ref(), ctx);
+            }
+        } else if entry_type.is_dir() {
+            if let Err(err) = extract_directory(&entry_name, ctx) {
+                entry_failed(&entry_name, err.as_ref(), ctx);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Extracts the file entries of the 7z archive at `path` the same way as zip
+/// entries, decrypting with the password field if one is set. Entries of a 7z
+/// archive are often compressed together, so they can only be read in order,
+/// and skipped entries are still decompressed.
+fn process_7z_file_thread(path: &Path, ctx: &mut ExtractContext) -> Result<(), Box<dyn Error>> {
+    let options = ctx.options;
+    let report = ctx.report;
+    let password = Password::from(options.password.as_str());
+    let mut archive = match SevenZReader::open(path, password) {
+        Ok(archive) => archive,
+        Err(sevenz_rust::Error::PasswordRequired) => {
+            report(Message::Error(
+                "Archive is encrypted, password required.".to_string(),
+            ));
+            ctx.summary.archives_failed += 1;
+            return Ok(());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    // An entry that fails is only logged, as in the other formats.
+    let mut cancelled = false;
+    let result = archive.for_each_entries(|entry, reader| {
+        if cancelled {
+            return Ok(false);
+        }
+        ctx.wait_while_paused();
+        if ctx.cancel_flag.load(Ordering::Relaxed) {
+            report(Message::Log("Extraction cancelled by user.\n".to_string()));
+            cancelled = true;
+            return Ok(false);
+        }
+
+        report(ctx.progress.advance());
+
+        let entry_name = entry.name().to_string();
+        if entry.is_directory() {
+            if let Err(err) = extract_directory(&entry_name, ctx) {
+                entry_failed(&entry_name, err.as_ref(), ctx);
+            }
+            return Ok(true);
+        }
+        let skipped = (options.skip_macosx && entry_name.contains("__MACOSX"))
+            || ctx.skip_excluded(&entry_name);
+        if !skipped {
+            let info = EntryInfo {
+                size: entry.size(),
+                modified: entry
+                    .has_last_modified_date
+                    .then(|| windows_time_to_naive(entry.last_modified_date().into()))
+                    .flatten(),
+                crc32: entry.has_crc.then_some(entry.crc as u32),
+                name: entry_name,
+                link_target: None,
+            };
+            if let Err(err) = extract_entry(reader, &info, ctx) {
+                entry_failed(&info.name, err.as_ref(), ctx);
+            }
+        }
+        // Whatever wasn't read has to be read past to get to the next entry.
+        io::copy(reader, &mut io::sink())?;
+        Ok(true)
+    });
+    match result {
+        Ok(()) => Ok(()),
+        Err(sevenz_rust::Error::PasswordRequired | sevenz_rust::Error::MaybeBadPassword(_)) => {
+            report(Message::Error(
+                "Wrong or missing password for this archive, skipping it.".to_string(),
+            ));
+            ctx.summary.archives_failed += 1;
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Recreates a folder entry under the output directory when `create_empty_dirs`
+/// is set and the folder structure is kept as it is in the archive. Folders
+/// that already exist are left alone.
+fn extract_directory(entry_name: &str, ctx: &mut ExtractContext) -> Result<(), Box<dyn Error>> {
+    let options = ctx.options;
+    let keeps_layout = options.preserve_structure
+        && !options.organize_by_extension
+        && ctx.extension_folders.is_none()
+        && ctx.name_template.is_none()
+        && !options.output_zip;
+    if !options.create_empty_dirs || !keeps_layout {
+        return Ok(());
+    }
+    if (options.skip_macosx && entry_name.contains("__MACOSX")) || ctx.is_excluded(entry_name) {
+        return Ok(());
+    }
+    let safe_path = match sanitize_entry_path(entry_name) {
+        Ok(path) => path,
+        Err(reason) => {
+            ctx.report_entry(Message::Warning(format!(
+                "Blocked unsafe entry: {} ({})",
+                entry_name, reason
+            )));
+            ctx.summary.keep_source = true;
+            return Ok(());
+        }
+    };
+    let relative: PathBuf = safe_path
+        .iter()
+        .skip(options.strip_components)
+        .map(|part| sanitize_filename(&part.to_string_lossy()))
+        .collect();
+    // One of the folders removed by `strip_components`.
+    if relative.as_os_str().is_empty() {
+        return Ok(());
+    }
+    let path = long_path(ctx.output_dir.join(relative));
+    if path.is_dir() {
+        return Ok(());
+    }
+    if options.dry_run {
+        ctx.log_entry(format!("Would create folder: {}\n", path.display()));
+        return Ok(());
+    }
+    fs::create_dir_all(&path)?;
+    ctx.log_entry(format!("Created folder: {}\n", path.display()));
+    Ok(())
+}
+
+/// Metadata about an archive entry that the filters and naming need.
+struct EntryInfo {
+    /// Path of the entry inside the archive.
+    name: String,
+    /// Uncompressed size in bytes.
+    size: u64,
+    /// Last-modified time as stored in the archive, if any.
+    modified: Option<NaiveDateTime>,
+    /// CRC32 of the contents as stored in the archive; only zips have one.
+    crc32: Option<u32>,
+    /// Where the entry points to, for symlink entries.
+    link_target: Option<PathBuf>,
+}
+
+/// Converts a zip timestamp (stored without a time zone) into a chrono value.
+fn zip_datetime_to_naive(datetime: zip::DateTime) -> Option<NaiveDateTime> {
+    NaiveDate::from_ymd_opt(
+        datetime.year().into(),
+        datetime.month().into(),
+        datetime.day().into(),
+    )?
+    .and_hms_opt(
+        datetime.hour().into(),
+        datetime.minute().into(),
+        datetime.second().into(),
+    )
+}
+
+/// Converts a Unix timestamp (as stored in tar headers) into local time, to
+/// match how zip timestamps are compared.
+fn unix_time_to_naive(seconds: u64) -> Option<NaiveDateTime> {
+    let seconds = i64::try_from(seconds).ok()?;
+    Local
+        .timestamp_opt(seconds, 0)
+        .single()
+        .map(|time| time.naive_local())
+}
+
+/// Converts a Windows file time (100 ns steps since 1601, as stored in 7z
+/// archives) into local time, like tar timestamps.
+fn windows_time_to_naive(ticks: u64) -> Option<NaiveDateTime> {
+    const SECONDS_FROM_1601_TO_1970: u64 = 11_644_473_600;
+    unix_time_to_naive((ticks / 10_000_000).checked_sub(SECONDS_FROM_1601_TO_1970)?)
+}
+
+/// Turns an entry's local modification time back into a file timestamp.
+fn naive_to_file_time(datetime: NaiveDateTime) -> Option<FileTime> {
+    Local
+        .from_local_datetime(&datetime)
+        .earliest()
+        .map(|time| FileTime::from_unix_time(time.timestamp(), 0))
+}
+
+/// Opens a tar archive for reading through a buffer of `buffer_size` bytes,
+/// decompressing it on the fly if needed.
+fn open_tar(
+    tar_path: &Path,
+    gzipped: bool,
+    buffer_size: usize,
+) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    let file = BufReader::with_capacity(buffer_size, File::open(tar_path)?);
+    let reader: Box<dyn Read> = if gzipped {
+        Box::new(GzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+/// Extracts a single file entry read from `reader`, whatever kind of archive it
+/// came from. Which files are extracted is decided by `should_extract`.
+/// Extracted files are saved into the output directory using their original file names,
+/// or under their relative entry path when `preserve_structure` is set.
+/// Entry names that could escape the output directory are blocked in either mode.
+/// Name collisions with existing files are resolved according to `duplicate_policy`.
+/// Entries outside the size bounds or the modified-date range are skipped.
+fn extract_entry(
+    reader: &mut dyn Read,
+    entry: &EntryInfo,
+    ctx: &mut ExtractContext,
+) -> Result<(), Box<dyn Error>> {
+    let options = ctx.options;
+    let entry_name = entry.name.as_str();
+    let size = entry.size;
+    let entry_path = Path::new(entry_name);
+
+    if let Some(selected) = &options.selected_entries {
+        if !selected.contains(entry_name) {
+            ctx.summary.files_skipped += 1;
+            return Ok(());
+        }
+    }
+
+    // Content detection replaces the extension filter; it runs further down
+    // once the cheaper checks have passed. Symlinks have no content of their own
+    // to look at, so they always go by extension.
+    if (!options.detect_by_content || entry.link_target.is_some())
+        && !should_extract(
+            entry_path,
+            ctx.filter_exts,
+            options.filter_mode,
+            options.case_sensitive,
+        )
+    {
+        ctx.summary.files_skipped += 1;
+        return Ok(());
+    }
+
+    if let Some(filter) = &ctx.name_filter {
+        if !filter.matches(entry_name) {
+            ctx.summary.files_skipped += 1;
+            return Ok(());
+        }
+    }
+
+    if let Some(target) = &entry.link_target {
+        // A new zip can't hold symlinks either, so they are left out of it too.
+        if !options.recreate_symlinks || !cfg!(unix) || options.output_zip {
+            ctx.summary.files_skipped += 1;
+            ctx.log_entry(format!("Skipped symlink: {}\n", entry_name));
+            return Ok(());
+        }
+        let escapes = target
+            .components()
+            .any(|part| !matches!(part, Component::Normal(_) | Component::CurDir));
+        if escapes {
+            ctx.report_entry(Message::Warning(format!(
+                "Blocked unsafe symlink: {} -> {}",
+                entry_name,
+                target.display()
+            )));
+            ctx.summary.files_skipped += 1;
+            ctx.summary.keep_source = true;
+            return Ok(());
+        }
+    }
+
+    if options.skip_empty_files && size == 0 && entry.link_target.is_none() {
+        ctx.summary.files_skipped += 1;
+        ctx.log_entry(format!("Skipped (empty): {}\n", entry_name));
+        return Ok(());
+    }
+
+    let too_small = ctx.min_size.is_some_and(|min| size < min);
+    let too_large = ctx.max_size.is_some_and(|max| size > max);
+    if too_small || too_large {
+        ctx.summary.files_skipped += 1;
+        ctx.log_entry(format!("Skipped (size): {}\n", entry_name));
+        return Ok(());
+    }
+
+    // Entries without a timestamp can't be judged, so the date range lets them through.
+    if let Some(modified) = entry.modified.map(|modified| modified.date()) {
+        let too_old = options.modified_after.is_some_and(|after| modified < after);
+        let too_new = options
+            .modified_before
+            .is_some_and(|before| modified > before);
+        if too_old || too_new {
+            ctx.summary.files_skipped += 1;
+            ctx.log_entry(format!("Skipped (date {}): {}\n", modified, entry_name));
+            return Ok(());
+        }
+    }
+
+    // Sniff the start of the entry when filtering by content. The bytes read
+    // here are put back in front of the rest of the stream before writing.
+    let mut head = Vec::new();
+    let mut type_note = String::new();
+    if options.detect_by_content && entry.link_target.is_none() {
+        (&mut *reader)
+            .take(CONTENT_SNIFF_LEN)
+            .read_to_end(&mut head)?;
+        let detected = infer::get(&head);
+        if !content_type_matches(detected, ctx.content_types) {
+            ctx.summary.files_skipped += 1;
+            return Ok(());
+        }
+        type_note = format!(
+            " [{}]",
+            detected.map_or("unknown type", |kind| kind.mime_type())
+        );
+    }
+    let mut reader = Cursor::new(head).chain(reader);
+
+    // Work out where the entry should land:
+    // - Names that are absolute or climb out with `..` are refused outright.
+    // - In flatten mode only the file name is used.
+    // - When preserving structure, the whole relative path is used.
+    let safe_path = match sanitize_entry_path(entry_name) {
+        Ok(path) => path,
+        Err(reason) => {
+            ctx.report_entry(Message::Warning(format!(
+                "Blocked unsafe entry: {} ({})",
+                entry_name, reason
+            )));
+            ctx.summary.keep_source = true;
+            return Ok(());
+        }
+    };
+    // Names made on another OS may hold characters this one doesn't allow.
+    let sanitized: PathBuf = safe_path
+        .iter()
+        .map(|part| sanitize_filename(&part.to_string_lossy()))
+        .collect();
+    if sanitized != safe_path {
+        ctx.log_entry(format!(
+            "Renamed entry not allowed as a file name here: {} -> {}\n",
+            entry_name,
+            sanitized.display()
+        ));
+    }
+    let safe_path = sanitized;
+    ctx.entry_index += 1;
+    let relative_path = if let Some(template) = ctx.name_template {
+        safe_path.file_name().and_then(|name| {
+            template.expand(
+                Path::new(name),
+                entry,
+                &ctx.current_archive,
+                ctx.entry_index,
+            )
+        })
+    } else if options.preserve_structure {
+        // Entries that would be left without a file name are ones in the stripped
+        // folders themselves.
+        let strip = options.strip_components;
+        if strip > 0 && safe_path.iter().count() <= strip {
+            ctx.log_entry(format!(
+                "Skipped (nothing left after stripping {} folder(s)): {}\n",
+                strip, entry_name
+            ));
+            ctx.summary.files_skipped += 1;
+            return Ok(());
+        }
+        Some(safe_path.iter().skip(strip).collect())
+    } else {
+        // Each prefix goes in front of the name, so with both it reads
+        // "archive1_2023_report.pdf".
+        safe_path.file_name().map(|name| {
+            let mut name = name.to_string_lossy().into_owned();
+            if options.parent_prefix {
+                if let Some(parent) = safe_path.parent().and_then(Path::file_name) {
+                    name = format!("{}_{}", parent.to_string_lossy(), name);
+                }
+            }
+            if options.source_prefix {
+                let stem = filesystem_safe(&archive_stem(&ctx.current_archive));
+                name = format!("{}_{}", stem, name);
+            }
+            PathBuf::from(name)
+        })
+    };
+    let Some(mut relative_path) = relative_path else {
+        ctx.report_entry(Message::Warning(format!(
+            "Skipping entry with invalid file name: {}",
+            entry_name
+        )));
+        return Ok(());
+    };
+    if options.name_transform != NameTransform::None {
+        if let Some(name) = relative_path.file_name() {
+            let transformed = options.name_transform.apply(&name.to_string_lossy());
+            relative_path.set_file_name(transformed);
+        }
+    }
+
+    let mut output_file_path = if let Some(folders) = ctx.extension_folders {
+        let Some(folder) = folders.folder_for(&relative_path) else {
+            ctx.log_entry(format!("Skipped (no matching group): {}\n", entry_name));
+            ctx.summary.files_skipped += 1;
+            return Ok(());
+        };
+        ctx.output_dir.join(folder).join(relative_path)
+    } else if options.organize_by_extension {
+        let folder = relative_path
+            .extension()
+            .map(|ext| sanitize_filename(&ext.to_string_lossy().to_lowercase()))
+            .unwrap_or_else(|| "no_extension".to_string());
+        ctx.output_dir.join(folder).join(relative_path)
+    } else {
+        ctx.output_dir.join(relative_path)
+    };
+    if !options.output_zip {
+        output_file_path = long_path(output_file_path);
+    }
+
+    // A slot is taken before writing, so workers running in parallel can't go
+    // over the limit, and given back if the entry doesn't end up extracted,
+    // e.g. because it is unchanged, a skipped duplicate or fails to write.
+    let mut reserved_extension = None;
+    if options.max_per_extension > 0 {
+        // Files without an extension share one counter under the empty key.
+        let mut ext = output_file_path
+            .extension()
+            .map(|ext| ext.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if !options.case_sensitive {
+            ext = ext.to_lowercase();
+        }
+        let mut counts = ctx
+            .per_extension
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let count = counts.entry(ext.clone()).or_insert(0);
+        if *count >= options.max_per_extension {
+            drop(counts);
+            ctx.log_entry(format!("Skipped (limit reached): {}\n", entry_name));
+            ctx.summary.files_skipped += 1;
+            return Ok(());
+        }
+        *count += 1;
+        reserved_extension = Some(ext);
+    }
+
+    let extracted_before = ctx.summary.files_extracted;
+    let result = write_entry(&mut reader, entry, output_file_path, &type_note, ctx);
+    if let Some(ext) = reserved_extension {
+        if ctx.summary.files_extracted == extracted_before {
+            let mut counts = ctx
+                .per_extension
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if let Some(count) = counts.get_mut(&ext) {
+                *count -= 1;
+            }
+        }
+    }
+    result
+}
+
+/// Second half of [`extract_entry`]: writes an entry that passed the filters
+/// to `output_file_path`, after the unchanged and duplicate checks, or only
+/// logs it in a dry run or diff.
+fn write_entry(
+    mut reader: &mut dyn Read,
+    entry: &EntryInfo,
+    mut output_file_path: PathBuf,
+    type_note: &str,
+    ctx: &mut ExtractContext,
+) -> Result<(), Box<dyn Error>> {
+    let options = ctx.options;
+    let report = ctx.report;
+    let entry_name = entry.name.as_str();
+    let size = entry.size;
+
+    // Compared before skipping unchanged files, which would hide the identical
+    // ones, and before duplicate handling, which would steer an existing path to
+    // a new name.
+    if options.diff_output {
+        let status = if !output_file_path.exists() {
+            ctx.summary.files_new += 1;
+            "New"
+        } else if is_unchanged(&output_file_path, entry, true, options.buffer_size()) {
+            ctx.summary.files_identical += 1;
+            "Identical"
+        } else {
+            ctx.summary.files_changed += 1;
+            "Changed"
+        };
+        ctx.log_entry(format!(
+            "{}: {}{}\n",
+            status,
+            output_file_path.display(),
+            type_note
+        ));
+        return Ok(());
+    }
+
+    if options.skip_unchanged
+        && !options.output_zip
+        && is_unchanged(
+            &output_file_path,
+            entry,
+            options.skip_unchanged_crc,
+            options.buffer_size(),
+        )
+    {
+        ctx.log_entry(format!("Unchanged, skipped: {}\n", entry_name));
+        ctx.summary.files_skipped += 1;
+        return Ok(());
+    }
+
+    if let Some(zip_output) = ctx.zip_output {
+        return add_to_output_zip(&mut reader, entry, output_file_path, zip_output, ctx);
+    }
+
+    let mut claimed = ctx
+        .claim_lock
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    // Paths are inside the new zip with `output_zip`; a dry run has no zip to check.
+    // Paths claimed earlier in the run are taken even in a dry run, where nothing
+    // is on disk, so the policy applies the same way across the whole batch.
+    let collides = claimed.contains(&output_file_path) || output_file_path.exists();
+    if !options.output_zip && collides {
+        let policy = match ctx.settled_duplicate_policy() {
+            Some(policy) => policy,
+            None => {
+                // Other workers keep going while the user makes up their mind.
+                drop(claimed);
+                let policy = ctx.duplicate_policy_for(&output_file_path);
+                claimed = ctx
+                    .claim_lock
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                policy
+            }
+        };
+        // Checked again, since another worker may have claimed paths meanwhile.
+        let claimed_this_run = claimed.contains(&output_file_path);
+        if claimed_this_run {
+            ctx.summary.run_duplicates += 1;
+        }
+        if claimed_this_run || output_file_path.exists() {
+            match policy {
+                DuplicatePolicy::Overwrite => {
+                    ctx.log_entry(format!(
+                        "Duplicate (overwrite): {}\n",
+                        output_file_path.display()
+                    ));
+                }
+                DuplicatePolicy::Rename => {
+                    let renamed = next_free_path(&output_file_path, |candidate| {
+                        claimed.contains(candidate) || candidate.exists()
+                    });
+                    ctx.log_entry(format!(
+                        "Duplicate (rename): {} -> {}\n",
+                        output_file_path.display(),
+                        renamed.display()
+                    ));
+                    output_file_path = renamed;
+                }
+                DuplicatePolicy::Skip | DuplicatePolicy::Ask => {
+                    ctx.log_entry(format!(
+                        "Duplicate (skip): {}\n",
+                        output_file_path.display()
+                    ));
+                    ctx.summary.files_skipped += 1;
+                    ctx.summary.keep_source = true;
+                    return Ok(());
+                }
+            }
+        }
+    }
+    claimed.insert(output_file_path.clone());
+
+    if options.dry_run {
+        ctx.summary.files_extracted += 1;
+        ctx.summary.bytes_written += size;
+        ctx.log_entry(format!(
+            "Would extract: {}{}\n",
+            output_file_path.display(),
+            type_note
+        ));
+        return Ok(());
+    }
+
+    let mut created_dirs = Vec::new();
+    if let Some(parent) = output_file_path.parent() {
+        created_dirs = parent
+            .ancestors()
+            .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        fs::create_dir_all(parent)?;
+    }
+    if let Some(target) = &entry.link_target {
+        return extract_symlink(target, output_file_path, entry_name, created_dirs, ctx);
+    }
+    let replaced = output_file_path.exists();
+    let retries = options.write_retries;
+    let file = with_retries(
+        retries,
+        entry_name,
+        &|message| ctx.report_entry(message),
+        || File::create(&output_file_path),
+    )?;
+    drop(claimed);
+    let mut outfile = Crc32Writer {
+        inner: BufWriter::with_capacity(options.buffer_size(), file),
+        hasher: crc32fast::Hasher::new(),
+    };
+    let copied = copy_with_progress(&mut reader, &mut outfile, entry_name, size, ctx);
+    let bytes = match copied {
+        Ok(bytes) => bytes,
+        // The zip reader checks the CRC itself once an entry is read to the end,
+        // and fails the read on a mismatch.
+        Err(err)
+            if options.verify_crc
+                && entry.crc32.is_some()
+                && err.kind() == io::ErrorKind::InvalidData =>
+        {
+            drop(outfile);
+            reject_corrupt(&output_file_path, &err.to_string(), ctx);
+            return Ok(());
+        }
+        Err(err) => {
+            // Leave no truncated file behind.
+            drop(outfile);
+            let _ = fs::remove_file(&output_file_path);
+            return Err(err.into());
+        }
+    };
+    // Flush explicitly so a failed final write is reported rather than lost on drop.
+    if let Err(err) = with_retries(
+        retries,
+        entry_name,
+        &|message| ctx.report_entry(message),
+        || outfile.flush(),
+    ) {
+        drop(outfile);
+        let _ = fs::remove_file(&output_file_path);
+        return Err(err.into());
+    }
+    let Crc32Writer { inner, hasher } = outfile;
+    drop(inner);
+    let actual_crc = hasher.finalize();
+    if let Some(expected_crc) = entry.crc32.filter(|_| options.verify_crc) {
+        if actual_crc != expected_crc {
+            let detail = format!("expected {:08x}, got {:08x}", expected_crc, actual_crc);
+            reject_corrupt(&output_file_path, &detail, ctx);
+            return Ok(());
+        }
+    }
+    if options.preserve_timestamps {
+        if let Some(mtime) = entry.modified.and_then(naive_to_file_time) {
+            if let Err(err) = filetime::set_file_mtime(&output_file_path, mtime) {
+                ctx.report_entry(Message::Warning(format!(
+                    "Could not set modification time of {}: {}",
+                    output_file_path.display(),
+                    err
+                )));
+            }
+        }
+    }
+    ctx.summary.files_extracted += 1;
+    ctx.summary.bytes_written += bytes;
+    ctx.log_entry(format!(
+        "Extracted: {}{}\n",
+        output_file_path.display(),
+        type_note
+    ));
+    let file = ExtractedFile {
+        path: output_file_path,
+        size: bytes,
+        archive: ctx.current_archive.clone(),
+        entry: entry_name.to_string(),
+        crc32: actual_crc,
+        replaced,
+        created_dirs,
+    };
+    if options.write_manifest {
+        ctx.manifest
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(file.clone());
+    }
+    report(Message::Extracted(file));
+    Ok(())
+}
+
+/// Whether `path` already holds this entry: same size and, with `compare_crc`,
+/// the same CRC32. Entries without a stored CRC (tar) are compared by size only.
+fn is_unchanged(path: &Path, entry: &EntryInfo, compare_crc: bool, buffer_size: usize) -> bool {
+    let Ok(metadata) = fs::metadata(path) else {
+        return false;
+    };
+    if !metadata.is_file() || metadata.len() != entry.size {
+        return false;
+    }
+    match entry.crc32.filter(|_| compare_crc) {
+        Some(expected) => file_crc32(path, buffer_size).is_ok_and(|crc| crc == expected),
+        None => true,
+    }
+}
+
+/// Computes the CRC32 of the file at `path`.
+fn file_crc32(path: &Path, buffer_size: usize) -> io::Result<u32> {
+    let mut file = BufReader::with_capacity(buffer_size, File::open(path)?);
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => hasher.update(&buffer[..read]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(hasher.finalize())
+}
+
+/// Reports a file whose contents failed the CRC check, deletes it if
+/// `delete_corrupt` is set, and counts it as a failed file, so the archive is
+/// kept with `delete_source`.
+fn reject_corrupt(path: &Path, detail: &str, ctx: &mut ExtractContext) {
+    ctx.report_entry(Message::Error(format!(
+        "CRC mismatch for {}: {}",
+        path.display(),
+        detail
+    )));
+    if ctx.options.delete_corrupt {
+        match fs::remove_file(path) {
+            Ok(()) => ctx.log_entry(format!("Deleted corrupt file: {}\n", path.display())),
+            Err(err) => ctx.report_entry(Message::Warning(format!(
+                "Could not delete corrupt file {}: {}",
+                path.display(),
+                err
+            ))),
+        }
+    }
+    ctx.summary.files_failed += 1;
+    ctx.summary.keep_source = true;
+}
+
+/// The new zip that entries are written into with `output_zip`, with the names
+/// it holds so far.
+struct ZipOutput {
+    writer: ZipWriter<File>,
+    names: HashSet<String>,
+}
+
+/// Writes an entry into the output zip under `path`. The zip has one writer,
+/// so entries from different workers are added one at a time. Name collisions
+/// within the new zip follow `duplicate_policy` (the prompt shows the path
+/// inside the zip), except that overwriting isn't
+/// possible in a zip, so it renames instead.
+fn add_to_output_zip(
+    reader: &mut dyn Read,
+    entry: &EntryInfo,
+    path: PathBuf,
+    zip_output: &Mutex<ZipOutput>,
+    ctx: &mut ExtractContext,
+) -> Result<(), Box<dyn Error>> {
+    let options = ctx.options;
+    // Zip names always use '/', whatever the OS.
+    let zip_name = |path: &Path| {
+        path.iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    };
+    let mut output = zip_output.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut name = zip_name(&path);
+    if output.names.contains(&name) {
+        let policy = match ctx.settled_duplicate_policy() {
+            Some(policy) => policy,
+            None => {
+                // Other workers keep adding files while the user makes up their mind.
+                drop(output);
+                let policy = ctx.duplicate_policy_for(&path);
+                output = zip_output.lock().unwrap_or_else(PoisonError::into_inner);
+                policy
+            }
+        };
+        if policy == DuplicatePolicy::Skip {
+            ctx.log_entry(format!("Duplicate (skip): {}\n", name));
+            ctx.summary.files_skipped += 1;
+            ctx.summary.keep_source = true;
+            return Ok(());
+        }
+        let renamed = next_free_path(&path, |candidate| {
+            output.names.contains(&zip_name(candidate))
+        });
+        let renamed = zip_name(&renamed);
+        ctx.log_entry(format!("Duplicate (rename): {} -> {}\n", name, renamed));
+        name = renamed;
+    }
+
+    let mut file_options = SimpleFileOptions::default().large_file(entry.size >= u32::MAX as u64);
+    if options.preserve_timestamps {
+        if let Some(modified) = entry.modified.and_then(naive_to_zip_datetime) {
+            file_options = file_options.last_modified_time(modified);
+        }
+    }
+    output.writer.start_file(name.as_str(), file_options)?;
+    let mut writer = Crc32Writer {
+        inner: &mut output.writer,
+        hasher: crc32fast::Hasher::new(),
+    };
+    let copied = copy_with_progress(reader, &mut writer, &entry.name, entry.size, ctx);
+    let actual_crc = writer.hasher.finalize();
+    // As when extracting to disk, the zip reader reports a bad CRC as invalid data.
+    let (bytes, crc_error) = match copied {
+        Ok(bytes) => {
+            let mismatch = entry
+                .crc32
+                .filter(|&expected| options.verify_crc && expected != actual_crc)
+                .map(|expected| format!("expected {:08x}, got {:08x}", expected, actual_crc));
+            (bytes, mismatch)
+        }
+        Err(err)
+            if options.verify_crc
+                && entry.crc32.is_some()
+                && err.kind() == io::ErrorKind::InvalidData =>
+        {
+            (0, Some(err.to_string()))
+        }
+        Err(err) => {
+            // Leave no half-written entry behind.
+            output.writer.abort_file()?;
+            return Err(err.into());
+        }
+    };
+    if let Some(detail) = crc_error {
+        ctx.report_entry(Message::Error(format!(
+            "CRC mismatch for {}: {}",
+            name, detail
+        )));
+        output.writer.abort_file()?;
+        ctx.summary.files_failed += 1;
+        ctx.summary.keep_source = true;
+        return Ok(());
+    }
+    output.names.insert(name.clone());
+    drop(output);
+
+    ctx.summary.files_extracted += 1;
+    ctx.summary.bytes_written += bytes;
+    ctx.log_entry(format!("Added to zip: {}\n", name));
+    Ok(())
+}
+
+/// Creates a symlink at `path` pointing to `target`. An existing file there is
+/// replaced, as the duplicate policy has already allowed overwriting it.
+fn extract_symlink(
+    target: &Path,
+    path: PathBuf,
+    entry_name: &str,
+    created_dirs: Vec<PathBuf>,
+    ctx: &mut ExtractContext,
+) -> Result<(), Box<dyn Error>> {
+    let replaced = path.symlink_metadata().is_ok();
+    if replaced {
+        fs::remove_file(&path)?;
+    }
+    if let Err(err) = create_symlink(target, &path) {
+        ctx.report_entry(Message::Warning(format!(
+            "Could not create symlink for {}: {}",
+            entry_name, err
+        )));
+        ctx.summary.keep_source = true;
+        return Ok(());
+    }
+    ctx.summary.files_extracted += 1;
+    ctx.log_entry(format!(
+        "Linked: {} -> {}\n",
+        path.display(),
+        target.display()
+    ));
+    // The link's own size, which is what undo compares against.
+    let size = path.symlink_metadata().map_or(0, |metadata| metadata.len());
+    let file = ExtractedFile {
+        path,
+        size,
+        archive: ctx.current_archive.clone(),
+        entry: entry_name.to_string(),
+        crc32: 0,
+        replaced,
+        created_dirs,
+    };
+    if ctx.options.write_manifest {
+        ctx.manifest
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(file.clone());
+    }
+    (ctx.report)(Message::Extracted(file));
+    Ok(())
+}
+
+/// Drops the calling thread to below normal priority.
+#[cfg(windows)]
+fn lower_thread_priority() -> io::Result<()> {
+    use winapi::um::processthreadsapi::{GetCurrentThread, SetThreadPriority};
+    use winapi::um::winbase::THREAD_PRIORITY_BELOW_NORMAL;
+    // SAFETY: the pseudo handle of the current thread is always valid.
+    let ok = unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_BELOW_NORMAL as i32) };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Raises the nice value to 10. On Linux this is per thread; on other Unix
+/// systems it lowers the whole process.
+#[cfg(unix)]
+fn lower_thread_priority() -> io::Result<()> {
+    // SAFETY: setpriority only reads its arguments.
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 10) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(windows, unix)))]
+fn lower_thread_priority() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "thread priority can't be changed on this platform",
+    ))
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, path: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+}
+
+/// Symlink entries are skipped before getting here on other platforms.
+#[cfg(not(unix))]
+fn create_symlink(_target: &Path, _path: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks are only recreated on Unix",
+    ))
+}
+
+/// Converts a timestamp into the zip format, which only covers 1980 to 2107.
+fn naive_to_zip_datetime(datetime: NaiveDateTime) -> Option<zip::DateTime> {
+    zip::DateTime::from_date_and_time(
+        u16::try_from(datetime.year()).ok()?,
+        datetime.month() as u8,
+        datetime.day() as u8,
+        datetime.hour() as u8,
+        datetime.minute() as u8,
+        datetime.second() as u8,
+    )
+    .ok()
+}
+
+/// Passes writes through to `inner`, keeping a CRC32 of the bytes written.
+struct Crc32Writer<W> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Write for Crc32Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// How many leading bytes of an entry are read to detect its type by content.
+const CONTENT_SNIFF_LEN: u64 = 8192;
+
+/// Checks a content-detected type against the wanted types. Each wanted type
+/// can be an extension ("pdf"), a full MIME type ("image/png") or a MIME
+/// category ("image"). Entries of unknown type only pass when nothing is listed.
+fn content_type_matches(detected: Option<infer::Type>, wanted: &[String]) -> bool {
+    if wanted.is_empty() {
+        return true;
+    }
+    let Some(kind) = detected else {
+        return false;
+    };
+    let mime = kind.mime_type();
+    let category = mime.split('/').next().unwrap_or(mime);
+    wanted
+        .iter()
+        .any(|wanted| wanted == kind.extension() || wanted == mime || wanted == category)
+}
+
+/// Size of each read while copying an entry to disk.
+const COPY_CHUNK_SIZE: usize = 64 * 1024;
+/// Minimum time between per-file progress messages, so large files don't flood the callback.
+const FILE_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Copies an entry to `writer` in fixed-size chunks, reporting the bytes
+/// written against `total` so the UI stays alive while a large file is written.
+/// A first report with nothing written yet announces the entry.
+/// Waits between chunks while the run is paused, so a large file holds off too.
+fn copy_with_progress(
+    reader: &mut dyn Read,
+    writer: &mut dyn Write,
+    entry_name: &str,
+    total: u64,
+    ctx: &ExtractContext,
+) -> io::Result<u64> {
+    let retries = ctx.options.write_retries;
+    let progress = ctx.progress;
+    let report = ctx.report;
+    let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+    let mut written = 0u64;
+    let mut last_report = Instant::now();
+    // Bytes added to the run total since the last report.
+    let mut unreported = 0u64;
+    report(Message::FileProgress {
+        name: entry_name.to_string(),
+        written,
+        total,
+    });
+    let mut last_space_check = Instant::now();
+    loop {
+        if last_space_check.elapsed() >= LOW_SPACE_CHECK_INTERVAL {
+            last_space_check = Instant::now();
+            ctx.wait_for_free_space();
+        }
+        ctx.pause.wait_while_paused(ctx.cancel_flag);
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        // Written piecewise rather than with `write_all` so a retry resumes exactly
+        // where the failed write stopped, without repeating or dropping bytes.
+        let mut pending = &buffer[..read];
+        while !pending.is_empty() {
+            let n = with_retries(
+                retries,
+                entry_name,
+                &|message| ctx.report_entry(message),
+                || writer.write(pending),
+            )?;
+            if n == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            pending = &pending[n..];
+        }
+        written += read as u64;
+        unreported += read as u64;
+        if last_report.elapsed() >= FILE_PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            report(Message::FileProgress {
+                name: entry_name.to_string(),
+                written,
+                total,
+            });
+            report(progress.add_bytes(std::mem::take(&mut unreported)));
+        }
+    }
+    // Final report so the UI knows this file is done.
+    report(progress.add_bytes(unreported));
+    report(Message::FileProgress {
+        name: entry_name.to_string(),
+        written,
+        total: total.max(written),
+    });
+    Ok(written)
+}
+
+/// Pause before the first retry of a failed write; doubled for each further attempt.
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// How to handle an output zip that already exists: the configured policy, or
+/// with [`DuplicatePolicy::Ask`] the user's answer. Skipping means the run
+/// can't go on, as there is nothing else to write into.
+fn existing_zip_policy(
+    path: &Path,
+    options: &ExtractOptions,
+    report: &dyn Fn(Message),
+) -> DuplicatePolicy {
+    if options.duplicate_policy != DuplicatePolicy::Ask {
+        return options.duplicate_policy;
+    }
+    let (reply, choice) = mpsc::channel();
+    report(Message::Conflict {
+        path: path.to_path_buf(),
+        reply,
+    });
+    choice
+        .recv()
+        .map_or(DuplicatePolicy::Skip, |choice| choice.policy)
+}
+
+/// Runs `op`, running it again after a short pause if it fails with an error
+/// that may go away on its own (a timeout, a dropped network share, ...), up to
+/// `retries` extra times. Errors that will not change on retry, such as
+/// permission denied or a full disk, are returned straight away.
+///
+/// Each retry is reported as a warning naming `entry_name`.
+fn with_retries<T>(
+    retries: u32,
+    entry_name: &str,
+    report: &dyn Fn(Message),
+    mut op: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Ok(value) => return Ok(value),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) if attempt < retries && !is_permanent_error(&err) => {
+                attempt += 1;
+                report(Message::Warning(format!(
+                    "Write failed for {} ({}), retrying ({}/{})",
+                    entry_name, err, attempt, retries
+                )));
+                thread::sleep(RETRY_BACKOFF * 2u32.saturating_pow(attempt - 1));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Whether `err` is one that retrying the same operation cannot fix.
+fn is_permanent_error(err: &io::Error) -> bool {
+    use io::ErrorKind::*;
+    matches!(
+        err.kind(),
+        PermissionDenied
+            | NotFound
+            | AlreadyExists
+            | InvalidInput
+            | InvalidData
+            | Unsupported
+            | OutOfMemory
+            | ReadOnlyFilesystem
+            | StorageFull
+            | FileTooLarge
+            | InvalidFilename
+            | IsADirectory
+            | NotADirectory
+            | DirectoryNotEmpty
+    )
+}
+
+/// Decides whether an entry passes the extension filter:
+/// - If no extensions were specified, every file is extracted.
+/// - In include mode, only files with an extension in `exts` are extracted.
+/// - In exclude mode, every file except those with an extension in `exts` is extracted.
+///
+/// Listed extensions may be compound: "tar.gz" matches `archive.tar.gz`, as
+/// does "gz". A name that is only the extension (".gz") doesn't match.
+///
+/// Unless `case_sensitive` is set, `exts` is expected to be lowercase and the
+/// entry's file name is lowercased before comparing.
+fn should_extract(
+    entry_path: &Path,
+    exts: &[String],
+    mode: FilterMode,
+    case_sensitive: bool,
+) -> bool {
+    if exts.is_empty() {
+        return true;
+    }
+    let listed = entry_path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .map(|name| {
+            let name = if case_sensitive {
+                name.to_string()
+            } else {
+                name.to_lowercase()
+            };
+            exts.iter().any(|ext| {
+                name.len() > ext.len() + 1
+                    && name.ends_with(ext.as_str())
+                    && name.as_bytes()[name.len() - ext.len() - 1] == b'.'
+            })
+        })
+        .unwrap_or(false);
+    match mode {
+        FilterMode::Include => listed,
+        FilterMode::Exclude => !listed,
+    }
+}
+
+/// Turns an archive entry name into a relative path that is safe to join onto
+/// the output directory ("zip slip" protection). Every entry goes through this
+/// before anything is written, so no extracted file can land outside the output
+/// directory. Rejected, with the reason as the error:
+/// - absolute paths and Windows drive or UNC prefixes (`/etc/passwd`, `C:\x`)
+/// - any `..` component, even one that would stay inside (`a/../b`)
+/// - names that are empty once `.` components are dropped
+///
+/// Backslashes are treated as separators too, since Windows-made archives use them.
+fn sanitize_entry_path(entry_name: &str) -> Result<PathBuf, String> {
+    let normalized = entry_name.replace('\\', "/");
+    let bytes = normalized.as_bytes();
+    let has_drive = bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':';
+    if normalized.starts_with('/') || has_drive {
+        return Err("absolute path".to_string());
+    }
+    let mut relative_path = PathBuf::new();
+    for component in Path::new(&normalized).components() {
+        match component {
+            Component::Normal(part) => relative_path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => return Err("parent directory component".to_string()),
+            Component::RootDir | Component::Prefix(_) => return Err("absolute path".to_string()),
+        }
+    }
+    if relative_path.as_os_str().is_empty() {
+        Err("empty path".to_string())
+    } else {
+        Ok(relative_path)
+    }
+}
+
+/// Names Windows reserves for devices, in any case and with any extension.
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Makes a single path component usable as a file name on this OS. Control
+/// characters are always replaced with '_'. On Windows, so are the reserved
+/// characters `<>:"/\|?*`; trailing dots and spaces (which Windows drops) are
+/// replaced too, and device names such as `CON` or `nul.txt` get a '_'
+/// appended to their stem.
+fn sanitize_filename(name: &str) -> String {
+    if !cfg!(windows) {
+        return name
+            .chars()
+            .map(|c| if c.is_control() { '_' } else { c })
+            .collect();
+    }
+    let mut name = filesystem_safe(name);
+    let trimmed_len = name.trim_end_matches(['.', ' ']).len();
+    if trimmed_len < name.len() {
+        let trailing = name.len() - trimmed_len;
+        name.truncate(trimmed_len);
+        name.push_str(&"_".repeat(trailing));
+    }
+    let stem = name.split('.').next().unwrap_or_default();
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.eq_ignore_ascii_case(reserved))
+    {
+        name.insert(stem.len(), '_');
+    }
+    name
+}
+
+/// Finds the first variant of `path` that `is_taken` accepts by appending a
+/// numeric suffix to the file stem, e.g. `report.pdf` becomes `report (1).pdf`,
+/// then `report (2).pdf`.
+fn next_free_path(path: &Path, is_taken: impl Fn(&Path) -> bool) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = path.extension().map(|s| s.to_string_lossy().into_owned());
+    let mut n = 1;
+    loop {
+        let file_name = match &ext {
+            Some(ext) => format!("{} ({}).{}", stem, n, ext),
+            None => format!("{} ({})", stem, n),
+        };
+        let candidate = path.with_file_name(file_name);
+        if !is_taken(&candidate) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_entry_path_blocks_crafted_names() {
+        for name in [
+            "../x",
+            "a/../b",
+            "/etc/passwd",
+            "C:\\x",
+            "\\\\server\\share\\x",
+            "./",
+            "",
+        ] {
+            assert!(
+                sanitize_entry_path(name).is_err(),
+                "{:?} should be blocked",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn sanitize_entry_path_accepts_relative_names() {
+        assert_eq!(
+            sanitize_entry_path("./docs\\2023/report.pdf").unwrap(),
+            Path::new("docs").join("2023").join("report.pdf")
+        );
+    }
+
+    fn exts(list: &[&str]) -> Vec<String> {
+        list.iter().map(|ext| ext.to_string()).collect()
+    }
+
+    #[test]
+    fn should_extract_matches_compound_extensions() {
+        let path = Path::new("backups/archive.tar.gz");
+        for filter in [exts(&["gz"]), exts(&["tar.gz"])] {
+            assert!(should_extract(path, &filter, FilterMode::Include, false));
+            assert!(!should_extract(path, &filter, FilterMode::Exclude, false));
+        }
+        assert!(!should_extract(
+            Path::new("notes.gz"),
+            &exts(&["tar.gz"]),
+            FilterMode::Include,
+            false
+        ));
+        assert!(should_extract(
+            Path::new("ARCHIVE.TAR.GZ"),
+            &exts(&["tar.gz"]),
+            FilterMode::Include,
+            false
+        ));
+    }
+
+    #[test]
+    fn should_extract_ignores_names_that_are_only_the_extension() {
+        for (name, filter) in [(".gz", "gz"), (".tar.gz", "tar.gz")] {
+            let path = Path::new(name);
+            assert!(!should_extract(
+                path,
+                &exts(&[filter]),
+                FilterMode::Include,
+                false
+            ));
+            assert!(should_extract(
+                path,
+                &exts(&[filter]),
+                FilterMode::Exclude,
+                false
+            ));
+        }
+    }
+
+    #[test]
+    fn should_extract_passes_everything_without_extensions() {
+        let path = Path::new("archive.tar.gz");
+        assert!(should_extract(path, &[], FilterMode::Include, false));
+        assert!(should_extract(path, &[], FilterMode::Exclude, false));
+    }
+
+    #[test]
+    fn decode_entry_name_keeps_utf8_flagged_names() {
+        let sjis = encoding_rs::SHIFT_JIS;
+        let name = "日本/報告.txt";
+        assert_eq!(
+            decode_entry_name(name.as_bytes(), name, true, Some(sjis)),
+            name
+        );
+
+        let (raw, _, _) = sjis.encode(name);
+        assert_eq!(decode_entry_name(&raw, "garbled", false, Some(sjis)), name);
+        assert_eq!(decode_entry_name(&raw, "garbled", false, None), "garbled");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn long_path_adds_prefix_to_deep_paths() {
+        let deep = format!(r"C:\out\{}report.pdf", "folder\\".repeat(30));
+        assert!(deep.len() > 260);
+        let prefixed = long_path(PathBuf::from(&deep));
+        assert!(prefixed.to_str().unwrap().starts_with(r"\\?\C:\out\"));
+
+        let unc = format!(r"\\server\share\{}report.pdf", "folder\\".repeat(30));
+        let prefixed = long_path(PathBuf::from(&unc));
+        assert!(prefixed
+            .to_str()
+            .unwrap()
+            .starts_with(r"\\?\UNC\server\share\"));
+
+        let short = PathBuf::from(r"C:\out\report.pdf");
+        assert_eq!(long_path(short.clone()), short);
+    }
+}
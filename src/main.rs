@@ -1,260 +1,2374 @@
 #![windows_subsystem = "windows"]
 
-use std::error::Error;
-use std::fs::{self, File};
-use std::io;
+use std::env;
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::mpsc;
-use std::thread;
+use std::time::{Duration, Instant};
 
 use std::sync::Arc;
-use zip::read::ZipArchive;
 
+use chrono::{Local, NaiveDate};
 use eframe::egui;
 use eframe::egui::IconData;
 use rfd::FileDialog;
+use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Eq, Clone, Copy)]
-enum InputType {
-    File,
-    Directory,
+use extract_filetype_from_zip_gui::{
+    available_space, default_worker_threads, expand_env_vars, extract, format_bytes,
+    list_zip_entries, resolve_output_path, undo_extraction, ArchiveKind, ConflictChoice,
+    DuplicatePolicy, ExtensionFolder, ExtractOptions, ExtractedFile, Extraction, FilterMode,
+    Message, NameTransform, Verbosity, ZipEntryListing,
+};
+
+/// Formats a duration for display, e.g. "45s", "3m 05s" or "1h 02m".
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs < 60 {
+        format!("{}s", secs)
+    } else if secs < 3600 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+    }
 }
 
+/// Usage notes shown in the help window, one paragraph each.
+const HELP_NOTES: &[&str] = &[
+    "Add archives (zip, tar, tar.gz, 7z), folders, patterns like C:\\data\\*.zip or \
+     archive URLs as inputs; files and folders can also be dropped onto the window. \
+     Folders are searched for archives, including subfolders when \"Include subfolders\" \
+     is checked.",
+    "Input and output paths may use environment variables, e.g. %USERPROFILE%\\Downloads \
+     or $HOME/archives; they are expanded when the run starts.",
+    "Leave the extensions field blank to extract every file. Otherwise list \
+     extensions without dots, separated by commas, e.g. \"pdf, jpg\".",
+    "The name pattern, size, date and per-extension limits all apply on top of the \
+     extension filter; an entry has to pass every one of them.",
+    "Archives made on macOS contain a __MACOSX folder of \"._\" metadata files. \
+     They are skipped unless \"Skip __MACOSX entries\" is unchecked.",
+    "Entries whose names would land outside the output directory (absolute paths \
+     or \"..\") are never extracted.",
+    "Symlink entries are skipped unless \"Recreate symlinks\" is checked, which only \
+     works on Unix. Links pointing at absolute paths or out through \"..\" are refused, \
+     and a link's target may not exist once extraction ends.",
+    "\"Undo Last Extraction\" deletes the files the last run wrote, keeping any that \
+     overwrote an existing file. It is only available until the next run or restart.",
+    "Use a dry run to see what would be extracted without writing anything. \
+     Hover over any control for a short description of what it does.",
+    "Shortcuts: Ctrl+O adds files, Ctrl+Shift+O picks the output, Ctrl+Enter starts, \
+     Esc cancels.",
+];
+
+/// Choices for the "Entry name encoding" box, as `(shown name, encoding label)`.
+/// An empty label means auto-detect.
+const NAME_ENCODINGS: [(&str, &str); 9] = [
+    ("Auto-detect", ""),
+    ("UTF-8", "utf-8"),
+    ("Shift-JIS (Japanese)", "shift_jis"),
+    ("GBK (Simplified Chinese)", "gbk"),
+    ("Big5 (Traditional Chinese)", "big5"),
+    ("EUC-KR (Korean)", "euc-kr"),
+    ("Windows-1251 (Cyrillic)", "windows-1251"),
+    ("Windows-1252 (Western)", "windows-1252"),
+    ("KOI8-R (Russian)", "koi8-r"),
+];
+
+/// Window title, also the app name settings are stored under.
+const APP_TITLE: &str = "Zip File Extractor";
+
+/// How many recent input and output paths are remembered.
+const MAX_RECENT_PATHS: usize = 10;
+
+/// Moves `path` to the front of `recent`, adding it if it's new and dropping
+/// the oldest entries beyond [`MAX_RECENT_PATHS`].
+fn remember_recent(recent: &mut Vec<String>, path: &str) {
+    let path = path.trim();
+    if path.is_empty() {
+        return;
+    }
+    recent.retain(|known| known != path);
+    recent.insert(0, path.to_string());
+    recent.truncate(MAX_RECENT_PATHS);
+}
+
+/// A "Recent" dropdown over `recent`; returns the path picked this frame, if any.
+fn recent_path_picker(ui: &mut egui::Ui, id: &str, recent: &[String]) -> Option<String> {
+    let mut picked = None;
+    ui.add_enabled_ui(!recent.is_empty(), |ui| {
+        egui::ComboBox::from_id_salt(id)
+            .selected_text("Recent")
+            .show_ui(ui, |ui| {
+                for path in recent {
+                    if ui.selectable_label(false, path).clicked() {
+                        picked = Some(path.clone());
+                    }
+                }
+            });
+    });
+    picked
+}
+
+/// How long a free-space reading of the output drive is shown before it is re-read.
+const FREE_SPACE_REFRESH: Duration = Duration::from_secs(3);
+
+/// Maximum number of lines kept in the on-screen log. Older lines are dropped
+/// so very large jobs don't grow the log without bound.
+const MAX_LOG_LINES: usize = 1000;
+
+/// A named, reusable set of extensions for the extensions field.
+#[derive(Clone, Serialize, Deserialize)]
+struct ExtensionPreset {
+    name: String,
+    extensions: String,
+}
+
+/// Presets offered on first launch.
+fn default_presets() -> Vec<ExtensionPreset> {
+    vec![
+        ExtensionPreset {
+            name: "Images".to_string(),
+            extensions: "jpg, png, gif".to_string(),
+        },
+        ExtensionPreset {
+            name: "Documents".to_string(),
+            extensions: "pdf, docx, txt".to_string(),
+        },
+    ]
+}
+
+/// Starting rows of the extension-to-folder table.
+fn default_extension_folders() -> Vec<ExtensionFolder> {
+    vec![
+        ExtensionFolder {
+            extensions: "pdf, docx, txt".to_string(),
+            folder: "Documents".to_string(),
+        },
+        ExtensionFolder {
+            extensions: "jpg, png, gif".to_string(),
+            folder: "Images".to_string(),
+        },
+    ]
+}
+
+/// Application state. The settings are persisted between launches through
+/// eframe's storage; fields marked `#[serde(skip)]` only live for the session.
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
 struct MyApp {
-    input_path: String,
+    /// Archives and folders to process in one run. Each entry is treated as a
+    /// file or a folder depending on what it points at.
+    input_paths: Vec<String>,
     /// Comma-separated list of file extensions (e.g., "pdf, jpg, png").
     /// If left empty, all files will be extracted.
     extensions: String,
+    filter_mode: FilterMode,
+    /// Saved extension sets the user can pick from.
+    presets: Vec<ExtensionPreset>,
+    /// Name of the preset last picked from the dropdown, if any.
+    selected_preset: Option<String>,
+    /// Name typed in for "Save current as preset".
+    #[serde(skip)]
+    new_preset_name: String,
     output_path: String,
-    input_type: InputType,
-    log: String,
-    /// Receiver for log messages coming from the background extraction thread.
-    log_rx: Option<mpsc::Receiver<String>>,
+    /// Input paths of recent runs, most recent first.
+    recent_inputs: Vec<String>,
+    /// Output paths of recent runs, most recent first.
+    recent_outputs: Vec<String>,
+    /// When enabled, the folder layout inside each zip is recreated under the
+    /// output directory instead of flattening every file into it.
+    preserve_structure: bool,
+    duplicate_policy: DuplicatePolicy,
+    /// When the input is a directory, also look for zips in its subfolders.
+    recursive: bool,
+    /// Open `.zip` entries found inside archives and extract their contents too.
+    extract_nested: bool,
+    /// How many archive levels deep nested extraction may go.
+    max_nested_depth: usize,
+    /// Only report what would be extracted, without writing any files.
+    dry_run: bool,
+    /// Match extensions exactly instead of ignoring case.
+    case_sensitive: bool,
+    /// Skip "__MACOSX" resource-fork entries added by macOS archivers.
+    skip_macosx: bool,
+    /// Smallest entry size to extract, in KB. Empty means no lower bound.
+    min_size_kb: String,
+    /// Largest entry size to extract, in KB. Empty means no upper bound.
+    max_size_kb: String,
+    /// Only extract entries last modified on or after this date.
+    modified_after: Option<NaiveDate>,
+    /// Only extract entries last modified on or before this date.
+    modified_before: Option<NaiveDate>,
+    /// Optional glob (e.g. "invoice_*.pdf") the entry file name must match.
+    name_pattern: String,
+    /// Treat the name pattern as a regular expression matched against the full entry name.
+    name_pattern_regex: bool,
+    /// Give each archive its own subfolder of the output directory, named after the archive.
+    output_per_zip: bool,
+    /// Decide which entries to extract by sniffing their content instead of by extension.
+    detect_by_content: bool,
+    /// Comma-separated types wanted in content mode, e.g. "image, pdf". Empty means every type.
+    content_types: String,
+    /// Extract into a new subfolder of the output directory named after the start time,
+    /// e.g. "2024-06-01_14-30-05".
+    dated_subfolder: bool,
+    /// Give extracted files the modification time stored in the archive.
+    preserve_timestamps: bool,
+    /// How many archives are extracted at the same time. Defaults to 1, which suits
+    /// spinning disks; SSDs can take up to about one per CPU.
+    worker_threads: usize,
+    /// Size in KB of the read and write buffers used while extracting.
+    buffer_size_kb: usize,
+    /// How many times a failed file write is retried before the entry is given up on.
+    write_retries: u32,
+    /// In flatten mode, start each file name with the stem of the archive it came from,
+    /// e.g. "archive1_report.pdf".
+    source_prefix: bool,
+    /// Delete each source archive once all of its entries were extracted without error.
+    delete_source: bool,
+    /// Sort extracted files into a subfolder per extension, e.g. "pdf/" and "jpg/".
+    organize_by_extension: bool,
+    /// Encoding of zip entry names from tools that do not write UTF-8, as a label such
+    /// as "Shift_JIS" or "GBK". Empty means auto-detect.
+    name_encoding: String,
+    /// Refuse to start when the expected output is larger than the free space on the
+    /// output drive, instead of only warning.
+    abort_on_low_space: bool,
+    /// Extract at most this many files of each extension in the whole run; 0 for no limit.
+    max_per_extension: usize,
+    /// Check each extracted zip entry against the CRC32 stored in the archive.
+    verify_crc: bool,
+    /// With `verify_crc`, delete files whose CRC doesn't match.
+    delete_corrupt: bool,
+    /// Write the matching entries into a new zip at `output_path` instead of extracting
+    /// them as loose files.
+    output_zip: bool,
+    /// Leave entries alone whose output file already exists with the same size.
+    skip_unchanged: bool,
+    /// With `skip_unchanged`, also require the existing file's CRC32 to match the archive's.
+    skip_unchanged_crc: bool,
+    /// How much is written to the log.
+    verbosity: Verbosity,
+    /// Recreate symlink entries as symlinks instead of skipping them. Only done on
+    /// Unix; Windows needs extra privileges for symlinks, so they are always skipped
+    /// there. Targets that are absolute or climb out with `..` are refused, and
+    /// nothing checks that the target exists.
+    recreate_symlinks: bool,
+    /// Write a manifest.csv into the output directory listing every extracted file.
+    write_manifest: bool,
+    /// With `write_manifest`, add to an existing manifest.csv instead of replacing it.
+    append_manifest: bool,
+    /// Template for output file names, e.g. "{zipstem}_{name}" or "{date}/{name}";
+    /// empty to name files by the other settings. Placeholders: {name}, {stem}, {ext},
+    /// {zipstem}, {index}, {date}. Replaces `preserve_structure` and the name
+    /// prefixes.
+    name_template: String,
+    /// Ask before starting when the archives hold more entries than this in total;
+    /// 0 for no limit.
+    max_total_entries: usize,
+    /// Comma-separated path fragments, e.g. "node_modules/, .git/"; entries whose full
+    /// name contains one are skipped.
+    exclude_paths: String,
+    /// Match the name pattern regardless of case; separate from `case_sensitive`,
+    /// which only covers extensions.
+    case_insensitive_names: bool,
+    /// Zip entries that expand to more than this many times their compressed size
+    /// are refused as possible zip bombs; 0 turns the check off for trusted archives.
+    max_compression_ratio: u64,
+    /// Resolve a relative `output_path` against the folder of the executable instead
+    /// of the working directory, for running from a USB stick.
+    output_relative_to_exe: bool,
+    /// With `preserve_structure`, also recreate the folder entries of archives, so
+    /// empty folders come out too.
+    create_empty_dirs: bool,
+    /// Run even when the output overlaps an input folder, which a recursive scan
+    /// could otherwise pick up on a later run.
+    allow_overlapping_output: bool,
+    /// Send files into the folders of `extension_folders` by extension, instead of
+    /// `organize_by_extension`'s folder per extension.
+    use_extension_folders: bool,
+    /// Rows of the extension-to-folder table.
+    extension_folders: Vec<ExtensionFolder>,
+    /// Folder for files whose extension isn't in `extension_folders`; blank for
+    /// the output directory itself.
+    default_extension_folder: String,
+    /// Change applied to every output file name, e.g. lowercasing it.
+    name_transform: NameTransform,
+    /// Leave out zero-byte entries, e.g. placeholder files.
+    skip_empty_files: bool,
+    /// Only read every entry through to check the archives' CRCs, like `unzip -t`,
+    /// and report which archives are intact. Nothing is written.
+    test_integrity: bool,
+    /// Refuse to run when the output directory already has anything in it, so new
+    /// files don't mix with existing ones.
+    require_empty_output: bool,
+    /// Compare each matching entry with what is already in the output directory and
+    /// report it as new, changed or identical. Nothing is written.
+    diff_output: bool,
+    /// Run extraction threads below normal OS priority, so a large job leaves the
+    /// machine responsive.
+    low_priority: bool,
+    /// With `preserve_structure`, leading folders removed from each entry path, like
+    /// `tar --strip-components`.
+    strip_components: usize,
+    /// With `use_extension_folders`, leave out files whose extension isn't in the
+    /// table instead of putting them into `default_extension_folder`.
+    skip_ungrouped_files: bool,
+    /// Pause the run when free space on the output drive drops below this many MB,
+    /// until space is freed. 0 turns it off.
+    pause_below_free_mb: u64,
+    /// In flatten mode, start each file name with the folder it was in inside the
+    /// archive, e.g. "2023_report.pdf" for docs/2023/report.pdf.
+    parent_prefix: bool,
+    /// Password used to decrypt encrypted entries. Left empty for plain archives.
+    /// Never written to disk.
+    #[serde(skip)]
+    password: String,
+    /// Lines shown in the log area, oldest first.
+    #[serde(skip)]
+    log: Vec<LogLine>,
+    /// Only log lines containing this (ignoring case) are shown.
+    #[serde(skip)]
+    log_filter: String,
+    /// The running (or just finished) extraction, whose messages are read every frame.
+    #[serde(skip)]
+    extraction: Option<Extraction>,
+    /// Latest `(processed, total)` entry counts reported by the background thread.
+    #[serde(skip)]
+    progress: Option<(usize, usize)>,
+    /// Latest `(entry name, bytes written, entry size)` for the file being written.
+    #[serde(skip)]
+    file_progress: Option<(String, u64, u64)>,
+    /// Latest `(bytes written, expected bytes)` for the whole run, for the time estimate.
+    #[serde(skip)]
+    bytes_progress: Option<(u64, u64)>,
+    /// When the current (or last) run was started.
+    #[serde(skip)]
+    started: Option<Instant>,
+    /// Files written by the current (or last) run, for the results list.
+    #[serde(skip)]
+    results: Vec<ExtractedFile>,
+    /// Contents of the zip opened with "Inspect", while its window is open.
+    #[serde(skip)]
+    inspection: Option<Inspection>,
+    /// Set while the "delete source archives" confirmation is showing.
+    #[serde(skip)]
+    confirm_delete: bool,
+    /// Set while the "undo last extraction" confirmation is showing.
+    #[serde(skip)]
+    confirm_undo: bool,
+    /// The collision being asked about with the "Ask" duplicate policy.
+    #[serde(skip)]
+    conflict: Option<PendingConflict>,
+    /// The oversized run being asked about, per `max_total_entries`.
+    #[serde(skip)]
+    large_run: Option<PendingLargeRun>,
+    /// The window title last sent to the viewport, so it's only updated on change.
+    #[serde(skip)]
+    shown_title: String,
+    /// Set while the help window is open.
+    #[serde(skip)]
+    show_help: bool,
     /// Flag indicating if extraction is running.
+    #[serde(skip)]
     is_extracting: bool,
+    /// When the current pause began; `None` while not paused.
+    #[serde(skip)]
+    paused_since: Option<Instant>,
+    /// Last free-space reading as `(output path, free bytes, when read)`, so the
+    /// drive isn't queried every frame.
+    #[serde(skip)]
+    free_space: Option<(String, Option<u64>, Instant)>,
 }
 
 impl Default for MyApp {
     fn default() -> Self {
         Self {
-            input_path: String::new(),
+            input_paths: vec![String::new()],
             extensions: String::new(),
+            filter_mode: FilterMode::Include,
+            presets: default_presets(),
+            selected_preset: None,
+            new_preset_name: String::new(),
             output_path: String::new(),
-            input_type: InputType::File,
-            log: String::new(),
-            log_rx: None,
+            recent_inputs: Vec::new(),
+            recent_outputs: Vec::new(),
+            preserve_structure: false,
+            duplicate_policy: DuplicatePolicy::Rename,
+            recursive: false,
+            extract_nested: false,
+            max_nested_depth: 3,
+            dry_run: false,
+            case_sensitive: false,
+            skip_macosx: true,
+            min_size_kb: String::new(),
+            max_size_kb: String::new(),
+            modified_after: None,
+            modified_before: None,
+            name_pattern: String::new(),
+            name_pattern_regex: false,
+            output_per_zip: false,
+            detect_by_content: false,
+            content_types: String::new(),
+            dated_subfolder: false,
+            preserve_timestamps: true,
+            worker_threads: 1,
+            buffer_size_kb: 64,
+            write_retries: 3,
+            source_prefix: false,
+            delete_source: false,
+            organize_by_extension: false,
+            name_encoding: String::new(),
+            abort_on_low_space: false,
+            max_per_extension: 0,
+            verify_crc: false,
+            delete_corrupt: false,
+            output_zip: false,
+            skip_unchanged: false,
+            skip_unchanged_crc: false,
+            verbosity: Verbosity::Verbose,
+            recreate_symlinks: false,
+            write_manifest: false,
+            append_manifest: false,
+            name_template: String::new(),
+            max_total_entries: 100_000,
+            exclude_paths: String::new(),
+            case_insensitive_names: false,
+            max_compression_ratio: 1000,
+            output_relative_to_exe: false,
+            create_empty_dirs: false,
+            allow_overlapping_output: false,
+            use_extension_folders: false,
+            extension_folders: default_extension_folders(),
+            default_extension_folder: "Other".to_string(),
+            name_transform: NameTransform::None,
+            skip_empty_files: false,
+            test_integrity: false,
+            require_empty_output: false,
+            diff_output: false,
+            low_priority: false,
+            strip_components: 0,
+            skip_ungrouped_files: false,
+            pause_below_free_mb: 0,
+            parent_prefix: false,
+            password: String::new(),
+            log: Vec::new(),
+            log_filter: String::new(),
+            extraction: None,
+            progress: None,
+            file_progress: None,
+            bytes_progress: None,
+            started: None,
+            results: Vec::new(),
+            inspection: None,
+            confirm_delete: false,
+            confirm_undo: false,
+            conflict: None,
+            large_run: None,
+            shown_title: String::new(),
+            show_help: false,
             is_extracting: false,
+            paused_since: None,
+            free_space: None,
         }
     }
 }
 
-/// This function runs in a background thread. It performs the extraction work
-/// and sends progress messages back through the provided channel.
-fn extract_files_thread(
-    input_path: String,
-    output_path: String,
-    extensions: String,
-    input_type: InputType,
-    sender: mpsc::Sender<String>,
-) -> Result<(), Box<dyn Error>> {
-    let output_path = PathBuf::from(&output_path);
-    fs::create_dir_all(&output_path)?;
-
-    // Split the extensions string into a vector.
-    // If the field is left empty, the vector will be empty.
-    let filter_exts: Vec<String> = extensions
-        .split(',')
-        .map(|s| s.trim().trim_start_matches('.').to_lowercase())
-        .filter(|s| !s.is_empty())
-        .collect();
-
-    // Log a message if no filtering is desired.
-    if filter_exts.is_empty() {
-        let _ = sender.send("No file extensions provided, extracting all files.\n".to_string());
-    }
-
-    let input_path = PathBuf::from(&input_path);
-    if input_type == InputType::Directory {
-        if !input_path.is_dir() {
-            let _ = sender.send(format!("{} is not a valid directory.\n", input_path.display()));
-            return Err(format!("{} is not a valid directory.", input_path.display()).into());
-        }
-        for entry in fs::read_dir(&input_path)? {
-            let entry = entry?;
-            let path = entry.path();
-            if path.is_file()
-                && path
-                    .extension()
-                    .and_then(|s| s.to_str())
-                    .map(|s| s.eq_ignore_ascii_case("zip"))
-                    .unwrap_or(false)
-            {
-                let _ = sender.send(format!("Processing zip file: {}\n", path.display()));
-                process_zip_file_thread(&path, &filter_exts, &output_path, &sender)?;
+impl MyApp {
+    /// Restores the settings saved by the previous session. A missing or
+    /// unreadable settings file simply yields the defaults.
+    fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        cc.storage
+            .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+            .unwrap_or_default()
+    }
+
+    fn extract_options(&self) -> ExtractOptions {
+        ExtractOptions {
+            input_paths: self.input_paths.clone(),
+            output_path: self.output_path.clone(),
+            extensions: self.extensions.clone(),
+            filter_mode: self.filter_mode,
+            preserve_structure: self.preserve_structure,
+            duplicate_policy: self.duplicate_policy,
+            recursive: self.recursive,
+            extract_nested: self.extract_nested,
+            max_nested_depth: self.max_nested_depth,
+            dry_run: self.dry_run,
+            case_sensitive: self.case_sensitive,
+            skip_macosx: self.skip_macosx,
+            min_size_kb: self.min_size_kb.clone(),
+            max_size_kb: self.max_size_kb.clone(),
+            modified_after: self.modified_after,
+            modified_before: self.modified_before,
+            name_pattern: self.name_pattern.clone(),
+            name_pattern_regex: self.name_pattern_regex,
+            output_per_zip: self.output_per_zip,
+            detect_by_content: self.detect_by_content,
+            content_types: self.content_types.clone(),
+            dated_subfolder: self.dated_subfolder,
+            preserve_timestamps: self.preserve_timestamps,
+            worker_threads: self.worker_threads,
+            buffer_size_kb: self.buffer_size_kb,
+            write_retries: self.write_retries,
+            source_prefix: self.source_prefix,
+            delete_source: self.delete_source,
+            organize_by_extension: self.organize_by_extension,
+            name_encoding: self.name_encoding.clone(),
+            abort_on_low_space: self.abort_on_low_space,
+            max_per_extension: self.max_per_extension,
+            verify_crc: self.verify_crc,
+            delete_corrupt: self.delete_corrupt,
+            output_zip: self.output_zip,
+            skip_unchanged: self.skip_unchanged,
+            skip_unchanged_crc: self.skip_unchanged_crc,
+            verbosity: self.verbosity,
+            recreate_symlinks: self.recreate_symlinks,
+            write_manifest: self.write_manifest,
+            append_manifest: self.append_manifest,
+            name_template: self.name_template.clone(),
+            max_total_entries: self.max_total_entries,
+            exclude_paths: self.exclude_paths.clone(),
+            case_insensitive_names: self.case_insensitive_names,
+            max_compression_ratio: self.max_compression_ratio,
+            output_relative_to_exe: self.output_relative_to_exe,
+            create_empty_dirs: self.create_empty_dirs,
+            allow_overlapping_output: self.allow_overlapping_output,
+            use_extension_folders: self.use_extension_folders,
+            extension_folders: self.extension_folders.clone(),
+            default_extension_folder: self.default_extension_folder.clone(),
+            name_transform: self.name_transform,
+            skip_empty_files: self.skip_empty_files,
+            test_integrity: self.test_integrity,
+            require_empty_output: self.require_empty_output,
+            diff_output: self.diff_output,
+            low_priority: self.low_priority,
+            strip_components: self.strip_components,
+            skip_ungrouped_files: self.skip_ungrouped_files,
+            pause_below_free_mb: self.pause_below_free_mb,
+            parent_prefix: self.parent_prefix,
+            password: self.password.clone(),
+            selected_entries: None,
+        }
+    }
+
+    /// Adds a file or folder to the input list, filling a blank row if there is
+    /// one. Paths already in the list are not added twice.
+    fn add_input(&mut self, path: &Path) {
+        let path = path.display().to_string();
+        if self.input_paths.contains(&path) {
+            return;
+        }
+        match self
+            .input_paths
+            .iter_mut()
+            .find(|input| input.trim().is_empty())
+        {
+            Some(blank) => *blank = path,
+            None => self.input_paths.push(path),
+        }
+    }
+
+    /// Keyboard shortcuts: Ctrl+O adds input files, Ctrl+Shift+O picks the output
+    /// folder, Ctrl+Enter starts extracting and Esc cancels a running extraction.
+    /// (Cmd instead of Ctrl on macOS.)
+    fn handle_shortcuts(&mut self, ctx: &egui::Context) {
+        let pressed = |modifiers, key| ctx.input_mut(|i| i.consume_key(modifiers, key));
+        if self.is_extracting {
+            if pressed(egui::Modifiers::NONE, egui::Key::Escape) {
+                self.cancel_extraction();
             }
+            return;
         }
-    } else {
-        if !input_path.is_file() {
-            let _ = sender.send(format!("{} is not a valid file.\n", input_path.display()));
-            return Err(format!("{} is not a valid file.", input_path.display()).into());
+        // The Shift variant goes first; Ctrl+O would match it as well.
+        if pressed(
+            egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+            egui::Key::O,
+        ) {
+            self.browse_output();
+        } else if pressed(egui::Modifiers::COMMAND, egui::Key::O) {
+            self.browse_input_files();
+        } else if pressed(egui::Modifiers::COMMAND, egui::Key::Enter) {
+            self.request_extraction();
         }
-        let _ = sender.send(format!("Processing zip file: {}\n", input_path.display()));
-        process_zip_file_thread(&input_path, &filter_exts, &output_path, &sender)?;
     }
-    let _ = sender.send("Extraction completed successfully.\n".to_string());
-    Ok(())
-}
 
-/// Processes a single zip file by extracting files.
-/// If `exts` is empty, every file is extracted;
-/// otherwise, only files whose extension (in lowercase) is in `exts` are extracted.
-/// Files whose names include "__MACOSX" are skipped.
-/// Extracted files are saved into `output_dir` using their original file names.
-fn process_zip_file_thread(
-    zip_path: &Path,
-    exts: &Vec<String>,
-    output_dir: &Path,
-    sender: &mpsc::Sender<String>,
-) -> Result<(), Box<dyn Error>> {
-    let file = File::open(zip_path)?;
-    let mut archive = ZipArchive::new(file)?;
-    for i in 0..archive.len() {
-        let mut zip_file = archive.by_index(i)?;
-        let entry_name = zip_file.name();
-
-        // Skip entries that are part of the "__MACOSX" metadata.
-        if entry_name.contains("__MACOSX") {
-            continue;
-        }
-
-        // Process only file entries.
-        if zip_file.is_file() {
-            let entry_path = Path::new(entry_name);
-
-            // Decide whether to extract this file:
-            // - If no extensions were specified, extract every file.
-            // - Otherwise, extract only files with an extension in `exts`.
-            let should_extract = if exts.is_empty() {
-                true
-            } else if let Some(entry_ext) = entry_path.extension().and_then(|s| s.to_str()) {
-                exts.contains(&entry_ext.to_lowercase())
+    /// Lets the user pick archives to add to the input list.
+    fn browse_input_files(&mut self) {
+        if let Some(paths) = FileDialog::new()
+            .add_filter("Archives", &["zip", "tar", "gz", "tgz", "7z"])
+            .add_filter("All files", &["*"])
+            .pick_files()
+        {
+            for path in paths {
+                self.add_input(&path);
+            }
+        }
+    }
+
+    /// Lets the user pick the output directory, or the zip file to create when
+    /// writing into a zip.
+    fn browse_output(&mut self) {
+        let picked = if self.output_zip {
+            FileDialog::new()
+                .add_filter("Zip archive", &["zip"])
+                .set_file_name("extracted.zip")
+                .save_file()
+        } else {
+            FileDialog::new().pick_folder()
+        };
+        if let Some(path) = picked {
+            self.output_path = path.display().to_string();
+        }
+    }
+
+    /// Settings for the one-click "Sort All by Type" run: every archive under the
+    /// inputs, flattened into one folder per extension, without overwriting.
+    fn apply_sort_by_type_preset(&mut self) {
+        self.recursive = true;
+        self.organize_by_extension = true;
+        // Extension folders would take the place of the extension-named ones.
+        self.use_extension_folders = false;
+        self.skip_ungrouped_files = false;
+        self.preserve_structure = false;
+        self.output_zip = false;
+        self.name_template.clear();
+        self.duplicate_policy = DuplicatePolicy::Rename;
+        // A preview, integrity test or comparison would write nothing.
+        self.dry_run = false;
+        self.test_integrity = false;
+        self.diff_output = false;
+    }
+
+    /// Starts a run with the current settings, asking first if it would delete
+    /// the source archives.
+    fn request_extraction(&mut self) {
+        if self.delete_source && !self.dry_run && !self.test_integrity && !self.diff_output {
+            self.confirm_delete = true;
+        } else {
+            self.start_extraction(self.extract_options());
+        }
+    }
+
+    /// Asks the running extraction to stop. A pending collision prompt is
+    /// dropped, which skips that entry, as is a pending large run question.
+    fn cancel_extraction(&mut self) {
+        if let Some(extraction) = &self.extraction {
+            extraction.cancel();
+        }
+        self.conflict = None;
+        self.large_run = None;
+    }
+
+    /// Shows the progress of a running extraction in the window title, so it can
+    /// be followed from the taskbar, e.g. "Zip File Extractor — 42%".
+    fn update_title(&mut self, ctx: &egui::Context) {
+        let title = match self.progress {
+            Some((done, total)) if self.is_extracting && total > 0 => {
+                format!("{} — {}%", APP_TITLE, done * 100 / total)
+            }
+            _ => APP_TITLE.to_string(),
+        };
+        if title != self.shown_title {
+            ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+            self.shown_title = title;
+        }
+    }
+
+    /// Adds archives and folders dropped onto the window to the input list.
+    fn handle_dropped_files(&mut self, ctx: &egui::Context) {
+        let dropped: Vec<PathBuf> = ctx.input(|i| {
+            i.raw
+                .dropped_files
+                .iter()
+                .filter_map(|file| file.path.clone())
+                .collect()
+        });
+        if dropped.is_empty() || self.is_extracting {
+            return;
+        }
+
+        for path in dropped {
+            if path.is_dir() || (path.is_file() && ArchiveKind::from_path(&path).is_some()) {
+                self.add_input(&path);
             } else {
-                false
+                self.push_log(
+                    LogLevel::Warning,
+                    &format!(
+                        "Ignored dropped item (not an archive or folder): {}",
+                        path.display()
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Drops the oldest lines once the log exceeds `MAX_LOG_LINES`.
+    fn trim_log(&mut self) {
+        let excess = self.log.len().saturating_sub(MAX_LOG_LINES);
+        self.log.drain(..excess);
+    }
+
+    /// Appends `text` to the log, one entry per line.
+    fn push_log(&mut self, level: LogLevel, text: &str) {
+        for line in text.lines() {
+            self.log.push(LogLine {
+                level,
+                text: line.to_string(),
+            });
+        }
+    }
+
+    /// The whole log as plain text, for copying and saving.
+    fn log_text(&self) -> String {
+        self.log
+            .iter()
+            .map(|line| format!("{}\n", line.text))
+            .collect()
+    }
+
+    /// Estimated time left in the current run, based on how fast bytes have been
+    /// written so far. `None` when no run is going or nothing is written yet.
+    fn time_remaining(&self) -> Option<Duration> {
+        let (written, total) = self.bytes_progress?;
+        let started = self.started?;
+        if !self.is_extracting || self.paused_since.is_some() || written == 0 || written >= total {
+            return None;
+        }
+        let elapsed = started.elapsed().as_secs_f64();
+        let remaining = elapsed * (total - written) as f64 / written as f64;
+        Some(Duration::from_secs_f64(remaining))
+    }
+
+    /// The output path as the next run will use it.
+    fn resolved_output_path(&self) -> PathBuf {
+        resolve_output_path(&self.output_path, self.output_relative_to_exe)
+    }
+
+    /// Free space on the drive of the output directory, re-read when the path
+    /// changes and every few seconds otherwise.
+    fn output_free_space(&mut self) -> Option<u64> {
+        if self.output_path.trim().is_empty() {
+            return None;
+        }
+        let path = self.resolved_output_path().display().to_string();
+        let stale = match &self.free_space {
+            Some((checked_path, _, checked_at)) => {
+                *checked_path != path || checked_at.elapsed() >= FREE_SPACE_REFRESH
+            }
+            None => true,
+        };
+        if stale {
+            let free = available_space(Path::new(&path));
+            self.free_space = Some((path, free, Instant::now()));
+        }
+        self.free_space.as_ref().and_then(|(_, free, _)| *free)
+    }
+
+    /// Pauses the running extraction, or resumes it if it is paused. Time spent
+    /// paused is left out of the time estimate.
+    fn toggle_pause(&mut self) {
+        match self.paused_since.take() {
+            Some(paused_since) => {
+                if let Some(started) = &mut self.started {
+                    *started += paused_since.elapsed();
+                }
+                if let Some(extraction) = &self.extraction {
+                    extraction.pause_control().resume();
+                }
+            }
+            None => {
+                if let Some(extraction) = &self.extraction {
+                    extraction.pause_control().pause();
+                }
+                self.paused_since = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Clears the output of the previous run and starts a new one in a
+    /// background thread.
+    fn start_extraction(&mut self, options: ExtractOptions) {
+        for input in &options.input_paths {
+            remember_recent(&mut self.recent_inputs, input);
+        }
+        remember_recent(&mut self.recent_outputs, &options.output_path);
+        self.log.clear();
+        self.progress = None;
+        self.file_progress = None;
+        self.bytes_progress = None;
+        self.started = Some(Instant::now());
+        self.results.clear();
+        self.paused_since = None;
+        self.is_extracting = true;
+        self.extraction = Some(Extraction::start(options));
+    }
+
+    /// Reads the entry list of a zip and opens the Inspect window for it.
+    fn inspect_archive(&mut self, path: PathBuf) {
+        match list_zip_entries(&path, &self.name_encoding) {
+            Ok(entries) => {
+                let checked = vec![true; entries.len()];
+                self.inspection = Some(Inspection {
+                    path,
+                    entries,
+                    checked,
+                });
+            }
+            Err(err) => {
+                let text = format!("Could not inspect {}: {}", path.display(), err);
+                self.push_log(LogLevel::Error, &text);
+            }
+        }
+    }
+
+    /// Asks before starting a run that deletes its source archives.
+    fn show_delete_confirmation(&mut self, ctx: &egui::Context) {
+        if !self.confirm_delete {
+            return;
+        }
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new("Delete source archives?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(
+                    "Each source archive will be permanently deleted once all of its \
+                     entries were extracted without error. Archives with any failure are kept.",
+                );
+                ui.horizontal(|ui| {
+                    confirmed = ui.button("Extract and Delete").clicked();
+                    cancelled = ui.button("Cancel").clicked();
+                });
+            });
+        if confirmed {
+            self.confirm_delete = false;
+            self.start_extraction(self.extract_options());
+        } else if cancelled {
+            self.confirm_delete = false;
+        }
+    }
+
+    /// Asks before deleting the files written by the last run.
+    fn show_undo_confirmation(&mut self, ctx: &egui::Context) {
+        if !self.confirm_undo {
+            return;
+        }
+        let mut confirmed = false;
+        let mut cancelled = false;
+        egui::Window::new("Undo last extraction?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "The {} file(s) written by the last run will be permanently deleted, \
+                     along with the folders created for them. Files that replaced an \
+                     existing file are kept.",
+                    self.results.len()
+                ));
+                ui.horizontal(|ui| {
+                    confirmed = ui.button("Delete Files").clicked();
+                    cancelled = ui.button("Cancel").clicked();
+                });
+            });
+        if confirmed {
+            self.confirm_undo = false;
+            self.undo_last_extraction();
+        } else if cancelled {
+            self.confirm_undo = false;
+        }
+    }
+
+    /// Deletes what the last run wrote and logs the outcome.
+    fn undo_last_extraction(&mut self) {
+        let undo = undo_extraction(&self.results);
+        self.results.clear();
+        for error in &undo.errors {
+            self.push_log(LogLevel::Error, &format!("Could not remove {}", error));
+        }
+        let mut text = format!(
+            "Undo: removed {} file(s) and {} folder(s).",
+            undo.files_removed, undo.folders_removed
+        );
+        if undo.files_kept > 0 {
+            text.push_str(&format!(
+                " Kept {} file(s) that replaced existing ones or were changed since.",
+                undo.files_kept
+            ));
+        }
+        self.push_log(LogLevel::Info, &text);
+    }
+
+    /// Asks whether to go ahead with a run that holds more entries than the limit.
+    fn show_large_run_confirmation(&mut self, ctx: &egui::Context) {
+        let Some(large_run) = &self.large_run else {
+            return;
+        };
+        let mut answer = None;
+        egui::Window::new("Continue with a large run?")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "The selected archives hold {} entries, more than the limit of {}. \
+                     Check that the right inputs are selected.",
+                    large_run.entries, large_run.limit
+                ));
+                ui.horizontal(|ui| {
+                    if ui.button("Continue").clicked() {
+                        answer = Some(true);
+                    }
+                    if ui.button("Cancel").clicked() {
+                        answer = Some(false);
+                    }
+                });
+            });
+        if let Some(answer) = answer {
+            if let Some(large_run) = self.large_run.take() {
+                let _ = large_run.reply.send(answer);
+            }
+        }
+    }
+
+    /// Asks what to do about the file a running extraction would overwrite.
+    fn show_conflict_prompt(&mut self, ctx: &egui::Context) {
+        let Some(conflict) = &mut self.conflict else {
+            return;
+        };
+        let mut chosen = None;
+        egui::Window::new("File already exists")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+            .show(ctx, |ui| {
+                ui.label(format!("{} already exists.", conflict.path.display()));
+                ui.checkbox(&mut conflict.apply_to_all, "Apply to all further conflicts");
+                ui.horizontal(|ui| {
+                    if ui.button("Overwrite").clicked() {
+                        chosen = Some(DuplicatePolicy::Overwrite);
+                    }
+                    if ui.button("Skip").clicked() {
+                        chosen = Some(DuplicatePolicy::Skip);
+                    }
+                    if ui.button("Rename").clicked() {
+                        chosen = Some(DuplicatePolicy::Rename);
+                    }
+                });
+            });
+        if let Some(policy) = chosen {
+            if let Some(conflict) = self.conflict.take() {
+                let _ = conflict.reply.send(ConflictChoice {
+                    policy,
+                    apply_to_all: conflict.apply_to_all,
+                });
+            }
+        }
+    }
+
+    /// The Inspect window: lists the entries of one zip with a checkbox each,
+    /// and extracts only the checked ones on request.
+    fn show_inspection(&mut self, ctx: &egui::Context) {
+        let Some(inspection) = &mut self.inspection else {
+            return;
+        };
+        let mut open = true;
+        let mut extract_selected = false;
+        let title = format!(
+            "Inspect: {}",
+            inspection
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default()
+        );
+        egui::Window::new(title)
+            .open(&mut open)
+            .default_width(560.0)
+            .show(ctx, |ui| {
+                let selected = inspection.checked.iter().filter(|&&c| c).count();
+                ui.horizontal(|ui| {
+                    if ui.button("Select all").clicked() {
+                        inspection.checked.fill(true);
+                    }
+                    if ui.button("Select none").clicked() {
+                        inspection.checked.fill(false);
+                    }
+                    ui.label(format!(
+                        "{} of {} selected",
+                        selected,
+                        inspection.entries.len()
+                    ));
+                });
+                egui::ScrollArea::vertical()
+                    .id_salt("inspect_entries")
+                    .max_height(400.0)
+                    .show(ui, |ui| {
+                        egui::Grid::new("inspect_grid")
+                            .striped(true)
+                            .num_columns(4)
+                            .show(ui, |ui| {
+                                ui.strong("Entry");
+                                ui.strong("Size");
+                                ui.strong("Compressed");
+                                ui.strong("Ratio");
+                                ui.end_row();
+                                for (entry, checked) in
+                                    inspection.entries.iter().zip(&mut inspection.checked)
+                                {
+                                    ui.checkbox(checked, &entry.name);
+                                    ui.label(format_bytes(entry.size));
+                                    ui.label(format_bytes(entry.compressed_size));
+                                    ui.label(format!("{:.0}%", entry.compression_ratio() * 100.0));
+                                    ui.end_row();
+                                }
+                            });
+                    });
+                // The picked entries are the filter, so the other filters don't apply.
+                ui.label(
+                    "Extracts the checked entries as they are, ignoring the filters above. \
+                     Possible zip bombs and unsafe paths are still refused.",
+                );
+                let can_extract = selected > 0 && !self.is_extracting;
+                extract_selected = ui
+                    .add_enabled(can_extract, egui::Button::new("Extract Selected"))
+                    .clicked();
+            });
+
+        if extract_selected {
+            let selected_entries = inspection
+                .entries
+                .iter()
+                .zip(&inspection.checked)
+                .filter(|(_, &checked)| checked)
+                .map(|(entry, _)| entry.name.clone())
+                .collect();
+            // Every other setting carries over from the main window.
+            let defaults = ExtractOptions::default();
+            let options = ExtractOptions {
+                input_paths: vec![inspection.path.display().to_string()],
+                selected_entries: Some(selected_entries),
+                extensions: defaults.extensions,
+                filter_mode: defaults.filter_mode,
+                name_pattern: defaults.name_pattern,
+                min_size_kb: defaults.min_size_kb,
+                max_size_kb: defaults.max_size_kb,
+                modified_after: defaults.modified_after,
+                modified_before: defaults.modified_before,
+                exclude_paths: defaults.exclude_paths,
+                detect_by_content: defaults.detect_by_content,
+                max_per_extension: defaults.max_per_extension,
+                skip_empty_files: defaults.skip_empty_files,
+                skip_macosx: false,
+                skip_ungrouped_files: false,
+                skip_unchanged: false,
+                extract_nested: false,
+                // Only part of the archive is extracted, so it has to stay, and it
+                // is extracted rather than only tested.
+                delete_source: false,
+                test_integrity: false,
+                ..self.extract_options()
             };
+            self.start_extraction(options);
+        }
+        if !open {
+            self.inspection = None;
+        }
+    }
 
-            if should_extract {
-                if let Some(file_name) = entry_path.file_name() {
-                    let output_file_path = output_dir.join(file_name);
-                    let mut outfile = File::create(&output_file_path)?;
-                    io::copy(&mut zip_file, &mut outfile)?;
-                    let _ = sender.send(format!("Extracted: {}\n", output_file_path.display()));
-                } else {
-                    let _ = sender.send(format!(
-                        "Warning: Skipping entry with invalid file name: {}\n",
-                        entry_name
+    /// The inputs, filters and output options, between the heading and the run
+    /// buttons.
+    fn show_settings(&mut self, ui: &mut egui::Ui) {
+        // Input paths (archives or folders), all processed in one run.
+        ui.label("Input Paths (archives, folders or URLs):");
+        let mut remove = None;
+        let mut inspect = None;
+        for (index, input) in self.input_paths.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(input);
+                if ui.button("Remove").clicked() {
+                    remove = Some(index);
+                }
+                // Only single zip files can be inspected.
+                let path = PathBuf::from(expand_env_vars(input.trim()));
+                let is_zip =
+                    path.is_file() && ArchiveKind::from_path(&path) == Some(ArchiveKind::Zip);
+                if ui
+                    .add_enabled(is_zip, egui::Button::new("Inspect"))
+                    .on_hover_text("List the entries and pick which ones to extract")
+                    .clicked()
+                {
+                    inspect = Some(path);
+                }
+            });
+        }
+        if let Some(index) = remove {
+            self.input_paths.remove(index);
+        }
+        if let Some(path) = inspect {
+            self.inspect_archive(path);
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Add Files").on_hover_text("Ctrl+O").clicked() {
+                self.browse_input_files();
+            }
+            if ui
+                .button("Add Folder")
+                .on_hover_text("Add a folder; every archive in it is processed.")
+                .clicked()
+            {
+                if let Some(path) = FileDialog::new().pick_folder() {
+                    self.add_input(&path);
+                }
+            }
+            if ui
+                .button("Add URL")
+                .on_hover_text(
+                    "Add a row for the http(s) address of an archive. It is downloaded \
+                     to a temporary file when the run starts, and removed afterwards.",
+                )
+                .clicked()
+            {
+                self.input_paths.push("https://".to_string());
+            }
+            if ui
+                .button("Add Pattern")
+                .on_hover_text(
+                    "Pick a folder and add a row matching the zips in it, e.g. \
+                     C:\\data\\*.zip. Edit the pattern to select exactly the archives \
+                     you want; * and ? match any characters.",
+                )
+                .clicked()
+            {
+                if let Some(path) = FileDialog::new().pick_folder() {
+                    self.input_paths
+                        .push(path.join("*.zip").to_string_lossy().into_owned());
+                }
+            }
+            if ui
+                .button("Add Row")
+                .on_hover_text("Add an empty row to type or paste a path into.")
+                .clicked()
+            {
+                self.input_paths.push(String::new());
+            }
+            if let Some(path) = recent_path_picker(ui, "recent_inputs", &self.recent_inputs) {
+                self.add_input(Path::new(&path));
+            }
+        });
+
+        // Only matters for folder inputs.
+        ui.checkbox(&mut self.recursive, "Include subfolders")
+            .on_hover_text("Also look for archives in the subfolders of folder inputs.");
+
+        // Extensions field.
+        ui.horizontal(|ui| {
+            ui.label("Extensions (comma-separated, e.g., pdf, jpg, png, if blank then all):");
+            ui.text_edit_singleline(&mut self.extensions).on_hover_text(
+                "Extensions to look for, without the dot. Leave blank to extract \
+                 every file.",
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Filter:");
+            ui.radio_value(&mut self.filter_mode, FilterMode::Include, "Include listed")
+                .on_hover_text("Extract only files with one of the listed extensions.");
+            ui.radio_value(&mut self.filter_mode, FilterMode::Exclude, "Exclude listed")
+                .on_hover_text("Extract every file except those with a listed extension.");
+        });
+
+        ui.checkbox(&mut self.case_sensitive, "Case-sensitive extensions")
+            .on_hover_text("Match extensions exactly, so \"PDF\" and \"pdf\" are different.");
+
+        // Content sniffing is slower since every entry has to be read, so it's opt-in.
+        ui.checkbox(
+            &mut self.detect_by_content,
+            "Detect file type by content instead of extension",
+        )
+        .on_hover_text(
+            "Pick files by their actual content (magic bytes) rather than by \
+             their extension. Slower, since every entry has to be read.",
+        );
+        if self.detect_by_content {
+            ui.horizontal(|ui| {
+                ui.label("Content types (e.g., image, pdf, audio/mpeg, if blank then all):");
+                ui.text_edit_singleline(&mut self.content_types)
+                    .on_hover_text(
+                        "Extensions (pdf), MIME types (audio/mpeg) or categories (image). \
+                         Leave blank for all.",
+                    );
+            });
+        }
+
+        // Optional file name pattern, combined with the extension filter.
+        ui.horizontal(|ui| {
+            if self.name_pattern_regex {
+                ui.label("Name regex (e.g., ^reports/.*\\.pdf$):");
+            } else {
+                ui.label("Name pattern (e.g., invoice_*.pdf, IMG_202?.jpg):");
+            }
+            ui.text_edit_singleline(&mut self.name_pattern)
+                .on_hover_text(
+                    "Only entries whose name matches are extracted, on top of the \
+                     extension filter. Leave blank to allow any name.",
+                );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Pattern type:");
+            ui.radio_value(&mut self.name_pattern_regex, false, "Glob")
+                .on_hover_text("Wildcards: * for any text, ? for one character.");
+            ui.radio_value(&mut self.name_pattern_regex, true, "Regex")
+                .on_hover_text("A regular expression, matched against the full entry path.");
+            ui.checkbox(&mut self.case_insensitive_names, "Case-insensitive names")
+                .on_hover_text(
+                    "Let the name pattern match regardless of case. Extensions have \
+                     their own case setting.",
+                );
+        });
+
+        // Leaves out whole folders inside the archives, e.g. node_modules.
+        ui.horizontal(|ui| {
+            ui.label("Exclude paths (e.g., node_modules/, .git/):");
+            ui.text_edit_singleline(&mut self.exclude_paths)
+                .on_hover_text(
+                    "Comma-separated. Entries whose path inside the archive contains any \
+                     of these are skipped. Matching is case-sensitive.",
+                );
+        });
+
+        // Optional last-modified date range.
+        ui.horizontal(|ui| {
+            date_bound_picker(ui, "Modified after", &mut self.modified_after);
+            date_bound_picker(ui, "Modified before", &mut self.modified_before);
+        });
+
+        // Optional size bounds for extracted entries.
+        ui.horizontal(|ui| {
+            ui.label("Min size (KB):");
+            ui.add(egui::TextEdit::singleline(&mut self.min_size_kb).desired_width(80.0))
+                .on_hover_text("Skip entries smaller than this. Leave blank for no minimum.");
+            ui.label("Max size (KB):");
+            ui.add(egui::TextEdit::singleline(&mut self.max_size_kb).desired_width(80.0))
+                .on_hover_text("Skip entries larger than this. Leave blank for no maximum.");
+        });
+
+        ui.checkbox(&mut self.skip_empty_files, "Skip empty files")
+            .on_hover_text("Leave out zero-byte files, such as placeholders.");
+
+        // Handy for grabbing a sample of each file type; 0 means no limit.
+        ui.horizontal(|ui| {
+            ui.label("Max files per extension:");
+            ui.add(egui::DragValue::new(&mut self.max_per_extension).range(0..=100_000))
+                .on_hover_text(
+                    "Stop extracting files of an extension once this many were \
+                     extracted. 0 means no limit.",
+                );
+            if self.max_per_extension == 0 {
+                ui.label("(unlimited)");
+            }
+        });
+
+        // Extension presets.
+        ui.horizontal(|ui| {
+            ui.label("Preset:");
+            egui::ComboBox::from_id_salt("extension_preset")
+                .selected_text(self.selected_preset.as_deref().unwrap_or("Select..."))
+                .show_ui(ui, |ui| {
+                    for preset in &self.presets {
+                        let selected =
+                            self.selected_preset.as_deref() == Some(preset.name.as_str());
+                        if ui.selectable_label(selected, &preset.name).clicked() {
+                            self.selected_preset = Some(preset.name.clone());
+                            self.extensions = preset.extensions.clone();
+                        }
+                    }
+                });
+            if ui
+                .add_enabled(
+                    self.selected_preset.is_some(),
+                    egui::Button::new("Delete preset"),
+                )
+                .clicked()
+            {
+                if let Some(name) = self.selected_preset.take() {
+                    self.presets.retain(|preset| preset.name != name);
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("New preset name:");
+            ui.text_edit_singleline(&mut self.new_preset_name);
+            let name = self.new_preset_name.trim().to_string();
+            if ui
+                .add_enabled(
+                    !name.is_empty(),
+                    egui::Button::new("Save current as preset"),
+                )
+                .clicked()
+            {
+                // Saving under an existing name updates that preset.
+                let preset = ExtensionPreset {
+                    name: name.clone(),
+                    extensions: self.extensions.clone(),
+                };
+                match self.presets.iter_mut().find(|p| p.name == name) {
+                    Some(existing) => *existing = preset,
+                    None => self.presets.push(preset),
+                }
+                self.selected_preset = Some(name);
+                self.new_preset_name.clear();
+            }
+        });
+
+        ui.checkbox(&mut self.skip_macosx, "Skip __MACOSX entries")
+            .on_hover_text(
+                "Archives made on macOS often contain a __MACOSX folder with resource-fork \
+                 metadata (\"._\" files). When checked, those entries are ignored; when \
+                 unchecked they are extracted like any other file, subject to the \
+                 extension filter.",
+            );
+
+        ui.checkbox(
+            &mut self.dry_run,
+            "Dry run (only list what would be extracted)",
+        )
+        .on_hover_text("Only log what would be extracted; nothing is written.");
+
+        // For incremental backups: shows what a re-extraction would change.
+        ui.checkbox(
+            &mut self.diff_output,
+            "Compare with existing output (new / changed / identical)",
+        )
+        .on_hover_text(
+            "Check every matching entry against the file already at its output path \
+             and report it as new, changed (size or CRC differs) or identical. \
+             Nothing is written.",
+        );
+
+        // For checking backups kept as archives.
+        ui.checkbox(
+            &mut self.test_integrity,
+            "Test archive integrity only (like unzip -t)",
+        )
+        .on_hover_text(
+            "Read every entry of every archive to check it for corruption and list \
+             which archives are OK. Nothing is extracted; the other settings are ignored.",
+        );
+
+        // Destructive, so starting a run with this set asks for confirmation first.
+        ui.checkbox(
+            &mut self.delete_source,
+            "Delete source archives after successful extraction",
+        )
+        .on_hover_text(
+            "Delete each source archive once all of its entries were \
+             extracted without error. You'll be asked to confirm when \
+             starting.",
+        );
+
+        // Whether to keep the folder layout found inside the archives.
+        ui.checkbox(
+            &mut self.preserve_structure,
+            "Preserve folder structure from zip",
+        )
+        .on_hover_text(
+            "Recreate the folders inside the archive under the output \
+             directory. When off, all files land directly in the output \
+             directory.",
+        );
+        ui.add_enabled_ui(self.preserve_structure, |ui| {
+            ui.checkbox(&mut self.create_empty_dirs, "Recreate empty folders")
+                .on_hover_text(
+                    "Also create the folders stored in the archive, so empty ones come \
+                     out too and the tree matches the archive exactly.",
+                );
+            // Archives of source code often wrap everything in one top folder.
+            ui.horizontal(|ui| {
+                ui.label("Strip leading folders:");
+                ui.add(egui::DragValue::new(&mut self.strip_components).range(0..=16))
+                    .on_hover_text(
+                        "Remove this many folders from the start of each entry path, \
+                         like tar --strip-components. With 1, \"project-1.0/src/main.c\" \
+                         becomes \"src/main.c\". Entries with no more folders than that \
+                         are skipped.",
+                    );
+            });
+        });
+        // Flattened names keep track of where each file came from.
+        ui.add_enabled_ui(!self.preserve_structure, |ui| {
+            ui.checkbox(
+                &mut self.source_prefix,
+                "Prefix file names with the archive name",
+            )
+            .on_hover_text(
+                "Start each file name with the name of its archive, e.g. \
+                 \"archive1_report.pdf\".",
+            );
+            ui.checkbox(
+                &mut self.parent_prefix,
+                "Prefix file names with their parent folder",
+            )
+            .on_hover_text(
+                "Start each file name with the folder it was in inside the archive, \
+                 e.g. \"2023_report.pdf\" for docs/2023/report.pdf. Files at the top \
+                 of the archive keep their name.",
+            );
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("File name template:");
+            ui.text_edit_singleline(&mut self.name_template)
+                .on_hover_text(
+                    "Optional, e.g. {zipstem}_{name} or {date}/{name}. Placeholders: \
+                     {name}, {stem}, {ext}, {zipstem} (archive name), {index} (count \
+                     within the archive), {date} (modified date). A / makes a subfolder. \
+                     Overrides the folder structure and prefix settings.",
+                );
+        });
+
+        // For pipelines that are picky about case or spaces.
+        ui.horizontal(|ui| {
+            ui.label("File names:");
+            ui.radio_value(&mut self.name_transform, NameTransform::None, "Unchanged");
+            ui.radio_value(
+                &mut self.name_transform,
+                NameTransform::Lowercase,
+                "Lowercase",
+            )
+            .on_hover_text("E.g. \"Report Q1.PDF\" becomes \"report q1.pdf\".");
+            ui.radio_value(
+                &mut self.name_transform,
+                NameTransform::ReplaceSpaces,
+                "Spaces to underscores",
+            )
+            .on_hover_text("E.g. \"Report Q1.PDF\" becomes \"Report_Q1.PDF\".");
+        });
+
+        ui.checkbox(
+            &mut self.organize_by_extension,
+            "Sort into a subfolder per extension",
+        )
+        .on_hover_text(
+            "Put files into a folder per extension, e.g. pdf/ and jpg/. Files \
+             without an extension go into no_extension/.",
+        );
+
+        // For mixed dumps where a folder per extension is too fine-grained.
+        ui.checkbox(
+            &mut self.use_extension_folders,
+            "Sort into folders by extension table",
+        )
+        .on_hover_text(
+            "Put files into the folder listed for their extension below, e.g. pdf into \
+             Documents/. Takes the place of the folder per extension.",
+        );
+        if self.use_extension_folders {
+            let mut remove = None;
+            egui::Grid::new("extension_folders_grid")
+                .num_columns(3)
+                .show(ui, |ui| {
+                    ui.label("Extensions");
+                    ui.label("Folder");
+                    ui.end_row();
+                    for (index, row) in self.extension_folders.iter_mut().enumerate() {
+                        ui.text_edit_singleline(&mut row.extensions)
+                            .on_hover_text("Comma-separated, e.g. jpg, png");
+                        ui.text_edit_singleline(&mut row.folder);
+                        if ui.button("Remove").clicked() {
+                            remove = Some(index);
+                        }
+                        ui.end_row();
+                    }
+                });
+            if let Some(index) = remove {
+                self.extension_folders.remove(index);
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Add Mapping").clicked() {
+                    self.extension_folders.push(ExtensionFolder::default());
+                }
+                ui.checkbox(&mut self.skip_ungrouped_files, "Skip other files")
+                    .on_hover_text(
+                        "Only extract files whose extension is in the table; the rest \
+                         are left out.",
+                    );
+                ui.add_enabled_ui(!self.skip_ungrouped_files, |ui| {
+                    ui.label("Other files go into:");
+                    ui.text_edit_singleline(&mut self.default_extension_folder)
+                        .on_hover_text(
+                            "Folder for extensions that aren't in the table. Leave blank \
+                             to put them straight into the output folder.",
+                        );
+                });
+            });
+        }
+
+        // Keeps same-named files from different archives apart.
+        ui.checkbox(
+            &mut self.output_per_zip,
+            "Extract each archive into its own subfolder",
+        )
+        .on_hover_text("Give every archive its own subfolder, named after the archive.");
+
+        ui.checkbox(&mut self.preserve_timestamps, "Preserve timestamps")
+            .on_hover_text(
+                "Give extracted files the modification time stored in the archive \
+                 instead of the current time.",
+            );
+
+        // Windows only allows symlinks with extra privileges, so it's Unix only.
+        ui.add_enabled_ui(cfg!(unix), |ui| {
+            ui.checkbox(&mut self.recreate_symlinks, "Recreate symlinks")
+                .on_hover_text(
+                    "Create symlink entries as symlinks. When off, they are skipped. \
+                     Only available on Unix.",
+                );
+        });
+
+        // Catches silent corruption, e.g. from archives on failing media.
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.verify_crc, "Verify CRC")
+                .on_hover_text(
+                    "Check each extracted zip entry against the checksum stored in \
+                     the archive.",
+                );
+            ui.add_enabled_ui(self.verify_crc, |ui| {
+                ui.checkbox(&mut self.delete_corrupt, "Delete files that fail")
+                    .on_hover_text(
+                        "Remove files whose checksum doesn't match instead of keeping \
+                         them.",
+                    );
+            });
+        });
+
+        // Guards against extracting into a folder that already has other content.
+        ui.checkbox(
+            &mut self.require_empty_output,
+            "Require empty output directory",
+        )
+        .on_hover_text(
+            "Refuse to start if the output folder already contains files or \
+             folders, including earlier dated subfolders.",
+        );
+
+        // Keeps separate runs from mixing in the same output directory.
+        ui.checkbox(
+            &mut self.dated_subfolder,
+            "Create dated subfolder for each run",
+        )
+        .on_hover_text(
+            "Extract into a new subfolder named after the start time, so runs \
+             don't mix.",
+        );
+
+        // Nested zip handling.
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.extract_nested, "Extract zips inside zips")
+                .on_hover_text(
+                    "Also extract the contents of zips found inside the archives, up \
+                     to the given depth.",
+                );
+            ui.add_enabled_ui(self.extract_nested, |ui| {
+                ui.label("Max depth:");
+                ui.add(egui::DragValue::new(&mut self.max_nested_depth).range(1..=10))
+                    .on_hover_text("How many levels of zips inside zips are opened.");
+            });
+        });
+
+        // Performance tuning; the defaults suit most machines.
+        egui::CollapsingHeader::new("Advanced").show(ui, |ui| {
+            // Archives processed in parallel; each worker handles one archive at a time.
+            ui.horizontal(|ui| {
+                ui.label("Max concurrent archives:");
+                ui.add(egui::DragValue::new(&mut self.worker_threads).range(1..=64))
+                    .on_hover_text(format!(
+                        "How many archives are extracted at the same time. Keep it at 1 \
+                         on spinning disks, which slow down when reading and writing \
+                         several files at once. On SSDs it can go up to about the number \
+                         of CPUs ({} here).",
+                        default_worker_threads()
                     ));
+            });
+            // For long jobs on a machine that is also used for other work.
+            ui.checkbox(&mut self.low_priority, "Run at low priority")
+                .on_hover_text(
+                    "Run the extraction below normal priority, so other programs stay \
+                     responsive during large jobs. Extraction may take longer when the \
+                     machine is busy.",
+                );
+            // Larger buffers help on network drives and spinning disks.
+            ui.horizontal(|ui| {
+                ui.label("I/O buffer size (KB):");
+                ui.add(egui::DragValue::new(&mut self.buffer_size_kb).range(4..=16384))
+                    .on_hover_text(
+                        "Size of the read and write buffers. Larger values can help on \
+                         network drives.",
+                    );
+            });
+            // Helps with flaky network shares; permanent errors are never retried.
+            ui.horizontal(|ui| {
+                ui.label("Write retries:");
+                ui.add(egui::DragValue::new(&mut self.write_retries).range(0..=10))
+                    .on_hover_text(
+                        "How often a failed write is retried before the file is given up \
+                         on. Errors like permission denied are never retried.",
+                    );
+            });
+            // Catches a mis-picked folder before thousands of files land.
+            ui.horizontal(|ui| {
+                ui.label("Ask above total entries:");
+                ui.add(
+                    egui::DragValue::new(&mut self.max_total_entries)
+                        .range(0..=100_000_000)
+                        .speed(100),
+                )
+                .on_hover_text(
+                    "Ask for confirmation before a run whose archives hold more entries \
+                     than this. 0 means never ask.",
+                );
+                if self.max_total_entries == 0 {
+                    ui.label("(never ask)");
                 }
+            });
+            // Protects against archives crafted to fill the disk.
+            ui.horizontal(|ui| {
+                ui.label("Max compression ratio:");
+                ui.add(
+                    egui::DragValue::new(&mut self.max_compression_ratio)
+                        .range(0..=1_000_000)
+                        .suffix("x"),
+                )
+                .on_hover_text(
+                    "Zip entries that expand to more than this many times their \
+                     compressed size are refused as possible zip bombs. Set to 0 for \
+                     trusted archives of very repetitive data.",
+                );
+                if self.max_compression_ratio == 0 {
+                    ui.label("(no limit)");
+                }
+            });
+            // Off by default so a recursive run can't end up re-reading its output.
+            ui.checkbox(
+                &mut self.allow_overlapping_output,
+                "Allow output inside an input folder",
+            )
+            .on_hover_text(
+                "Normally a run is refused when the output is inside one of the input \
+                 folders, or the other way round, since extracted archives could then \
+                 be picked up as input later. Check this if that is intended.",
+            );
+        });
+
+        // Password for encrypted archives.
+        ui.horizontal(|ui| {
+            ui.label("Password (if encrypted):");
+            ui.add(egui::TextEdit::singleline(&mut self.password).password(true))
+                .on_hover_text("Used for encrypted zip entries. It is never saved.");
+        });
+
+        // Only needed for zips from legacy tools whose names come out garbled.
+        ui.horizontal(|ui| {
+            ui.label("Entry name encoding:");
+            let selected = NAME_ENCODINGS
+                .iter()
+                .find(|(_, label)| *label == self.name_encoding)
+                .map_or(self.name_encoding.as_str(), |(name, _)| name);
+            egui::ComboBox::from_id_salt("name_encoding")
+                .selected_text(selected)
+                .show_ui(ui, |ui| {
+                    for (name, label) in NAME_ENCODINGS {
+                        ui.selectable_value(&mut self.name_encoding, label.to_string(), name);
+                    }
+                });
+        });
+
+        // Makes re-running into the same output folder fast.
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.skip_unchanged, "Skip unchanged files")
+                .on_hover_text(
+                    "Leave out entries whose output file already exists with the same size.",
+                );
+            ui.add_enabled_ui(self.skip_unchanged, |ui| {
+                ui.checkbox(&mut self.skip_unchanged_crc, "Compare CRC too")
+                    .on_hover_text("Slower, as existing files are read in full.");
+            });
+        });
+
+        // A record of the run for scripts; there is no folder for it in zip mode.
+        ui.add_enabled_ui(!self.output_zip, |ui| {
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.write_manifest, "Write manifest.csv")
+                    .on_hover_text(
+                        "List every extracted file in manifest.csv in the output \
+                         directory: source archive, entry path, output path, size and CRC.",
+                    );
+                ui.add_enabled_ui(self.write_manifest, |ui| {
+                    ui.checkbox(&mut self.append_manifest, "Append to existing")
+                        .on_hover_text(
+                            "Add to the manifest of earlier runs instead of replacing it.",
+                        );
+                });
+            });
+        });
+
+        // How to handle files that already exist in the output directory.
+        ui.horizontal(|ui| {
+            ui.label("If file exists:");
+            ui.radio_value(
+                &mut self.duplicate_policy,
+                DuplicatePolicy::Rename,
+                "Rename",
+            )
+            .on_hover_text("Keep both, adding a number: report (1).pdf.");
+            ui.radio_value(
+                &mut self.duplicate_policy,
+                DuplicatePolicy::Overwrite,
+                "Overwrite",
+            )
+            .on_hover_text("Replace the existing file.");
+            ui.radio_value(&mut self.duplicate_policy, DuplicatePolicy::Skip, "Skip")
+                .on_hover_text("Keep the existing file and leave the entry out.");
+            ui.radio_value(&mut self.duplicate_policy, DuplicatePolicy::Ask, "Ask")
+                .on_hover_text(
+                    "Ask for each file that already exists. Best for small jobs; the \
+                     run waits while the question is showing.",
+                );
+        });
+
+        // Output directory.
+        ui.horizontal(|ui| {
+            ui.label(if self.output_zip {
+                "Output Zip:"
+            } else {
+                "Output Directory:"
+            });
+            ui.text_edit_singleline(&mut self.output_path)
+                .on_hover_text(
+                    "Where extracted files are written. It is created if it doesn't \
+                     exist.",
+                );
+            if ui.button("Browse").on_hover_text("Ctrl+Shift+O").clicked() {
+                self.browse_output();
+            }
+            if let Some(path) = recent_path_picker(ui, "recent_outputs", &self.recent_outputs) {
+                self.output_path = path;
+            }
+        });
+        // For running from a USB stick, where drive letters and mount points change.
+        ui.horizontal(|ui| {
+            ui.checkbox(
+                &mut self.output_relative_to_exe,
+                "Relative to the program's folder",
+            )
+            .on_hover_text(
+                "Take a relative output path from the folder this program is in, \
+                 rather than from where it was started. Absolute paths are used as is.",
+            );
+            // Also shows what environment variables in the path expand to.
+            let resolved = self.resolved_output_path();
+            if resolved != Path::new(self.output_path.trim()) {
+                ui.weak(format!("= {}", resolved.display()));
             }
+        });
+        if let Some(free) = self.output_free_space() {
+            ui.label(format!(
+                "Free space on output drive: {}",
+                format_bytes(free)
+            ));
         }
+        // Repackages the matching entries instead of extracting them.
+        ui.checkbox(
+            &mut self.output_zip,
+            "Write into a new zip instead of a folder",
+        )
+        .on_hover_text(
+            "Write the matching files into a new zip at the output path \
+             instead of extracting them.",
+        );
+        ui.checkbox(
+            &mut self.abort_on_low_space,
+            "Don't start if the output won't fit on the drive",
+        )
+        .on_hover_text(
+            "Stop before writing anything when the matching files are larger \
+             than the free space. Otherwise you only get a warning.",
+        );
+        // Catches the drive filling up during the run, e.g. from other programs.
+        ui.horizontal(|ui| {
+            ui.label("Pause when free space drops below (MB):");
+            ui.add(egui::DragValue::new(&mut self.pause_below_free_mb).range(0..=1_000_000))
+                .on_hover_text(
+                    "Pause the run when the output drive gets this low on space, so no \
+                     file is left half-written. It resumes by itself once space is \
+                     freed, or press Resume to go on anyway. Set to 0 to turn this off.",
+                );
+            if self.pause_below_free_mb == 0 {
+                ui.label("(off)");
+            }
+        });
     }
-    Ok(())
+}
+
+/// An archive opened in the Inspect window, with a checkbox per entry.
+struct Inspection {
+    path: PathBuf,
+    entries: Vec<ZipEntryListing>,
+    checked: Vec<bool>,
+}
+
+/// A collision the background thread is waiting on an answer for.
+struct PendingConflict {
+    path: PathBuf,
+    reply: mpsc::Sender<ConflictChoice>,
+    apply_to_all: bool,
+}
+
+/// A run waiting for the go-ahead because its archives hold many entries.
+struct PendingLargeRun {
+    entries: usize,
+    limit: usize,
+    reply: mpsc::Sender<bool>,
+}
+
+/// How a log line is highlighted.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum LogLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+/// One line of the on-screen log.
+struct LogLine {
+    level: LogLevel,
+    text: String,
 }
 
 impl eframe::App for MyApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, eframe::APP_KEY, self);
+    }
+
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Drain any log messages coming from the background thread.
-        if let Some(rx) = &self.log_rx {
+        // Drain any log messages coming from the background thread. The run
+        // is taken out meanwhile so the log can be written to, and put back
+        // unless the thread has finished.
+        if let Some(extraction) = self.extraction.take() {
+            let mut connected = true;
             loop {
-                match rx.try_recv() {
-                    Ok(msg) => self.log.push_str(&msg),
+                match extraction.try_next() {
+                    Ok(Message::Log(text)) => self.push_log(LogLevel::Info, &text),
+                    Ok(Message::Progress { done, total }) => {
+                        self.progress = Some((done, total));
+                    }
+                    Ok(Message::Bytes { written, total }) => {
+                        self.bytes_progress = Some((written, total));
+                    }
+                    Ok(Message::FileProgress {
+                        name,
+                        written,
+                        total,
+                    }) => {
+                        self.file_progress = Some((name, written, total));
+                    }
+                    Ok(Message::Conflict { path, reply }) => {
+                        self.conflict = Some(PendingConflict {
+                            path,
+                            reply,
+                            apply_to_all: false,
+                        });
+                    }
+                    Ok(Message::ConfirmLargeRun {
+                        entries,
+                        limit,
+                        reply,
+                    }) => {
+                        self.large_run = Some(PendingLargeRun {
+                            entries,
+                            limit,
+                            reply,
+                        });
+                    }
+                    Ok(Message::Extracted(file)) => self.results.push(file),
+                    Ok(Message::Warning(text)) => {
+                        self.push_log(LogLevel::Warning, &format!("Warning: {}", text));
+                    }
+                    Ok(Message::Error(text)) => {
+                        self.push_log(LogLevel::Error, &format!("Error: {}", text));
+                    }
+                    Ok(Message::Done(_)) => {
+                        self.is_extracting = false;
+                        self.conflict = None;
+                        self.large_run = None;
+                        self.paused_since = None;
+                    }
                     Err(mpsc::TryRecvError::Empty) => break,
                     Err(mpsc::TryRecvError::Disconnected) => {
                         self.is_extracting = false;
-                        self.log_rx = None;
+                        self.paused_since = None;
+                        connected = false;
                         break;
                     }
                 }
             }
+            // The run can pause and resume itself, e.g. when the output drive is
+            // low on space.
+            if connected && self.is_extracting {
+                let paused = extraction.pause_control().is_paused();
+                if paused && self.paused_since.is_none() {
+                    self.paused_since = Some(Instant::now());
+                } else if !paused {
+                    if let (Some(paused_since), Some(started)) =
+                        (self.paused_since.take(), &mut self.started)
+                    {
+                        *started += paused_since.elapsed();
+                    }
+                }
+            }
+            if connected {
+                self.extraction = Some(extraction);
+            }
+            self.trim_log();
+            // Keep repainting so progress shows up even without user input.
+            ctx.request_repaint_after(Duration::from_millis(100));
         }
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.heading("Zip File Extractor");
+        self.update_title(ctx);
+        self.handle_dropped_files(ctx);
+        self.handle_shortcuts(ctx);
 
-            // Choose whether the input is a file or a directory.
+        // The buttons, progress and log stay in view however far the settings
+        // are scrolled.
+        egui::TopBottomPanel::bottom("run_panel").show(ctx, |ui| {
             ui.horizontal(|ui| {
-                ui.label("Input Type:");
-                ui.radio_value(&mut self.input_type, InputType::File, "File");
-                ui.radio_value(&mut self.input_type, InputType::Directory, "Directory");
-            });
+                // Button to start extraction.
+                if ui
+                    .button("Extract Files")
+                    .on_hover_text("Ctrl+Enter")
+                    .clicked()
+                    && !self.is_extracting
+                {
+                    self.request_extraction();
+                }
 
-            // Input path (zip file or directory).
-            ui.horizontal(|ui| {
-                ui.label("Input Path:");
-                ui.text_edit_singleline(&mut self.input_path);
-                if ui.button("Browse").clicked() {
-                    let selected = if self.input_type == InputType::File {
-                        FileDialog::new().pick_file()
-                    } else {
-                        FileDialog::new().pick_folder()
-                    };
-                    if let Some(path) = selected {
-                        self.input_path = path.display().to_string();
+                // The most common workflow in one click; the settings stay adjustable.
+                if ui
+                    .add_enabled(!self.is_extracting, egui::Button::new("Sort All by Type"))
+                    .on_hover_text(
+                        "Turn on \"Include subfolders\" and \"Sort into a subfolder per \
+                         extension\", rename files that already exist, and start extracting.",
+                    )
+                    .clicked()
+                {
+                    self.apply_sort_by_type_preset();
+                    self.request_extraction();
+                }
+
+                // Button to stop a running extraction.
+                if ui
+                    .add_enabled(self.is_extracting, egui::Button::new("Cancel"))
+                    .on_hover_text("Esc")
+                    .clicked()
+                {
+                    self.cancel_extraction();
+                }
+
+                // Holds the run between entries (or chunks of a large file).
+                let pause_label = if self.paused_since.is_some() {
+                    "Resume"
+                } else {
+                    "Pause"
+                };
+                if ui
+                    .add_enabled(self.is_extracting, egui::Button::new(pause_label))
+                    .clicked()
+                {
+                    self.toggle_pause();
+                }
+
+                // Button to reveal the results in the system file manager.
+                let can_open = !self.is_extracting && !self.output_path.trim().is_empty();
+                if ui
+                    .add_enabled(can_open, egui::Button::new("Open Output Folder"))
+                    .clicked()
+                {
+                    let output = self.resolved_output_path();
+                    if let Err(err) = open::that(&output) {
+                        let text =
+                            format!("Could not open output folder {}: {}", output.display(), err);
+                        self.push_log(LogLevel::Error, &text);
                     }
                 }
-            });
 
-            // Extensions field.
-            ui.horizontal(|ui| {
-                ui.label("Extensions (comma-separated, e.g., pdf, jpg, png, if blank then all):");
-                ui.text_edit_singleline(&mut self.extensions);
-            });
+                // Reverses the last run, e.g. after extracting to the wrong folder.
+                let can_undo = !self.is_extracting && !self.results.is_empty();
+                if ui
+                    .add_enabled(can_undo, egui::Button::new("Undo Last Extraction"))
+                    .on_hover_text(
+                        "Delete the files written by the last run and the folders created \
+                         for them. Files that already existed are not touched.",
+                    )
+                    .clicked()
+                {
+                    self.confirm_undo = true;
+                }
 
-            // Output directory.
-            ui.horizontal(|ui| {
-                ui.label("Output Directory:");
-                ui.text_edit_singleline(&mut self.output_path);
-                if ui.button("Browse").clicked() {
-                    if let Some(path) = FileDialog::new().pick_folder() {
-                        self.output_path = path.display().to_string();
+                // A sign of life while a large file is copied and the log stands still.
+                if self.is_extracting {
+                    if self.paused_since.is_some() {
+                        ui.label("Paused");
+                    } else {
+                        ui.spinner();
+                        ui.label("Extracting...");
+                        ctx.request_repaint();
                     }
                 }
             });
 
-            // Button to start extraction.
-            if ui.button("Extract Files").clicked() && !self.is_extracting {
-                // Clear the previous log and start extraction in a new thread.
-                self.log.clear();
-                let input_path = self.input_path.clone();
-                let output_path = self.output_path.clone();
-                let extensions = self.extensions.clone();
-                let input_type = self.input_type;
-                let (tx, rx) = mpsc::channel::<String>();
-                self.log_rx = Some(rx);
-                self.is_extracting = true;
-                thread::spawn(move || {
-                    let _ = extract_files_thread(input_path, output_path, extensions, input_type, tx);
-                });
+            // Overall progress of the current (or last) run.
+            if let Some((processed, total)) = self.progress {
+                let fraction = if total == 0 {
+                    1.0
+                } else {
+                    processed as f32 / total as f32
+                };
+                let paused = if self.paused_since.is_some() {
+                    "Paused: "
+                } else {
+                    ""
+                };
+                ui.add(egui::ProgressBar::new(fraction).text(format!(
+                    "{}{} / {} entries ({:.0}%)",
+                    paused,
+                    processed,
+                    total,
+                    fraction * 100.0
+                )));
+            }
+
+            // Estimated time remaining, extrapolated from the bytes written so far.
+            if let Some(remaining) = self.time_remaining() {
+                ui.label(format!("About {} remaining", format_duration(remaining)));
+            }
+
+            // Progress within the file currently being written; hidden once it finishes.
+            if let Some((name, written, total)) = &self.file_progress {
+                if written < total {
+                    let fraction = *written as f32 / *total as f32;
+                    ui.add(egui::ProgressBar::new(fraction).text(format!(
+                        "{}: {} / {} ({:.0}%)",
+                        name,
+                        format_bytes(*written),
+                        format_bytes(*total),
+                        fraction * 100.0
+                    )));
+                } else if *total == 0 && *written > 0 {
+                    // Downloads whose size the server didn't say.
+                    ui.label(format!("{}: {}", name, format_bytes(*written)));
+                }
+            }
+
+            // Files written by the run. Clicking a name opens it with the
+            // system's default application.
+            if !self.results.is_empty() {
+                let mut open_error = None;
+                egui::CollapsingHeader::new(format!("Extracted files ({})", self.results.len()))
+                    .show(ui, |ui| {
+                        egui::ScrollArea::vertical()
+                            .id_salt("results")
+                            .max_height(200.0)
+                            .show(ui, |ui| {
+                                egui::Grid::new("results_grid")
+                                    .striped(true)
+                                    .num_columns(3)
+                                    .show(ui, |ui| {
+                                        ui.strong("File");
+                                        ui.strong("Size");
+                                        ui.strong("From");
+                                        ui.end_row();
+                                        for file in &self.results {
+                                            let name = file
+                                                .path
+                                                .file_name()
+                                                .map(|name| name.to_string_lossy())
+                                                .unwrap_or_default();
+                                            if ui
+                                                .link(name)
+                                                .on_hover_text(file.path.display().to_string())
+                                                .clicked()
+                                            {
+                                                if let Err(err) = open::that(&file.path) {
+                                                    open_error = Some(format!(
+                                                        "Could not open {}: {}",
+                                                        file.path.display(),
+                                                        err
+                                                    ));
+                                                }
+                                            }
+                                            ui.label(format_bytes(file.size));
+                                            ui.label(file.archive.display().to_string());
+                                            ui.end_row();
+                                        }
+                                    });
+                            });
+                    });
+                if let Some(text) = open_error {
+                    self.push_log(LogLevel::Error, &text);
+                }
             }
 
             ui.separator();
 
+            // What is being worked on right now; the log scrolls too fast to follow.
+            if self.is_extracting {
+                if let Some((name, _, _)) = &self.file_progress {
+                    ui.label(egui::RichText::new(format!("Now extracting: {}", name)).strong());
+                }
+            }
+
             // Log output in a scrollable area.
-            ui.label("Log:");
+            ui.horizontal(|ui| {
+                ui.label("Log:");
+                // Clearing mid-run would throw away live output.
+                if ui
+                    .add_enabled(!self.is_extracting, egui::Button::new("Clear Log"))
+                    .clicked()
+                {
+                    self.log.clear();
+                }
+                if ui.button("Copy Log").clicked() {
+                    ctx.copy_text(self.log_text());
+                }
+                if ui.button("Save Log").clicked() {
+                    if let Some(path) = FileDialog::new()
+                        .set_file_name("extraction_log.txt")
+                        .add_filter("Text file", &["txt"])
+                        .save_file()
+                    {
+                        if let Err(err) = fs::write(&path, self.log_text()) {
+                            let text =
+                                format!("Error: Could not save log to {}: {}", path.display(), err);
+                            self.push_log(LogLevel::Error, &text);
+                        }
+                    }
+                }
+                ui.separator();
+                ui.label("Detail:");
+                ui.radio_value(&mut self.verbosity, Verbosity::Quiet, "Quiet")
+                    .on_hover_text("Only messages about whole archives and the summary.");
+                ui.radio_value(&mut self.verbosity, Verbosity::Normal, "Normal")
+                    .on_hover_text("Also warnings and errors about single files.");
+                ui.radio_value(&mut self.verbosity, Verbosity::Verbose, "Verbose")
+                    .on_hover_text("Also a line for every file extracted or skipped.");
+            });
+            // Only changes what is shown; copying and saving still take the whole log.
+            let filter = self.log_filter.trim().to_lowercase();
+            ui.horizontal(|ui| {
+                ui.label("Search:");
+                ui.text_edit_singleline(&mut self.log_filter)
+                    .on_hover_text("Show only the log lines that contain this text.");
+                if ui
+                    .add_enabled(!filter.is_empty(), egui::Button::new("Clear"))
+                    .clicked()
+                {
+                    self.log_filter.clear();
+                }
+                if !filter.is_empty() {
+                    let shown = self
+                        .log
+                        .iter()
+                        .filter(|line| line.text.to_lowercase().contains(&filter))
+                        .count();
+                    ui.label(format!("{} of {} lines", shown, self.log.len()));
+                }
+            });
+            // Errors in red and warnings in yellow so they stand out among the
+            // "Extracted:" lines.
             egui::ScrollArea::vertical()
                 .max_height(300.0)
+                .auto_shrink([false, false])
                 .stick_to_bottom(true)
                 .show(ui, |ui| {
-                    ui.add(
-                        egui::TextEdit::multiline(&mut self.log)
-                            .desired_rows(20)
-                            .desired_width(600.0),
-                    );
+                    let visuals = ui.visuals().clone();
+                    let shown = self.log.iter().filter(|line| {
+                        filter.is_empty() || line.text.to_lowercase().contains(&filter)
+                    });
+                    for line in shown {
+                        let text = egui::RichText::new(&line.text).monospace();
+                        let text = match line.level {
+                            LogLevel::Info => text,
+                            LogLevel::Warning => text.color(visuals.warn_fg_color),
+                            LogLevel::Error => text.color(visuals.error_fg_color),
+                        };
+                        ui.label(text);
+                    }
                 });
         });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.heading("Zip File Extractor");
+                if ui.button("?").on_hover_text("Usage notes").clicked() {
+                    self.show_help = true;
+                }
+            });
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .show(ui, |ui| self.show_settings(ui));
+        });
+
+        self.show_inspection(ctx);
+        self.show_delete_confirmation(ctx);
+        self.show_undo_confirmation(ctx);
+        self.show_conflict_prompt(ctx);
+        self.show_large_run_confirmation(ctx);
+        egui::Window::new("Help")
+            .open(&mut self.show_help)
+            .default_width(480.0)
+            .show(ctx, |ui| {
+                for note in HELP_NOTES {
+                    ui.label(*note);
+                    ui.add_space(4.0);
+                }
+            });
+
+        // Show a hint over the whole window while files are dragged over it.
+        if ctx.input(|i| !i.raw.hovered_files.is_empty()) {
+            let painter = ctx.layer_painter(egui::LayerId::new(
+                egui::Order::Foreground,
+                egui::Id::new("file_drop_overlay"),
+            ));
+            let screen_rect = ctx.screen_rect();
+            painter.rect_filled(screen_rect, 0.0, egui::Color32::from_black_alpha(192));
+            painter.text(
+                screen_rect.center(),
+                egui::Align2::CENTER_CENTER,
+                "Drop zip or folder here",
+                egui::TextStyle::Heading.resolve(&ctx.style()),
+                egui::Color32::WHITE,
+            );
+        }
+    }
+}
+
+/// A checkbox that enables an optional date bound, followed by a date picker
+/// for it while enabled.
+fn date_bound_picker(ui: &mut egui::Ui, label: &str, date: &mut Option<NaiveDate>) {
+    let mut enabled = date.is_some();
+    if ui.checkbox(&mut enabled, label).changed() {
+        *date = enabled.then(|| Local::now().date_naive());
+    }
+    if let Some(date) = date {
+        ui.add(egui_extras::DatePickerButton::new(date).id_salt(label));
+    }
+}
+
+const CLI_USAGE: &str = "\
+Usage: Zip_File_Extractor --input <archive or folder> --output <folder> [options]
+
+Runs without the GUI when any arguments are given.
+
+Options:
+  --input <path>    Archive (zip, tar, tar.gz, 7z), folder of archives, glob pattern such as
+                    'data/*.zip', http(s) URL of an archive, or - to read a zip from
+                    standard input (can be repeated)
+  --output <path>   Folder to extract into
+  --ext <list>      Comma-separated extensions to extract, e.g. pdf,jpg (default: all)
+  --recursive       Also look for archives in subfolders of input folders
+  --low-priority    Run below normal priority to keep the machine responsive
+  --allow-overlap   Run even if the output is inside an input folder (or vice versa)
+  --require-empty   Refuse to run if the output folder is not empty
+  --test            Only check the archives for corruption; nothing is written
+  --diff            Report which files are new, changed or identical in the output; nothing
+                    is written
+  --help            Show this message";
+
+/// Builds the extraction options from command-line arguments. Settings that
+/// have no flag keep their defaults. Returns `Ok(None)` when help was requested.
+fn parse_cli_args(args: &[String]) -> Result<Option<ExtractOptions>, String> {
+    let mut options = ExtractOptions::default();
+    let mut inputs = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("Missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--input" => inputs.push(value()?),
+            "--output" => options.output_path = value()?,
+            "--ext" => options.extensions = value()?,
+            "--recursive" => options.recursive = true,
+            "--low-priority" => options.low_priority = true,
+            "--allow-overlap" => options.allow_overlapping_output = true,
+            "--require-empty" => options.require_empty_output = true,
+            "--test" => options.test_integrity = true,
+            "--diff" => options.diff_output = true,
+            "--help" | "-h" => return Ok(None),
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+
+    if inputs.is_empty() {
+        return Err("Missing --input".to_string());
+    }
+    if options.output_path.is_empty() && !options.test_integrity {
+        return Err("Missing --output".to_string());
+    }
+    options.input_paths = inputs;
+    Ok(Some(options))
+}
+
+/// Runs an extraction without the GUI, printing the log to stdout.
+/// Returns the process exit code: non-zero if the run, an archive or a file failed.
+fn run_headless(args: &[String]) -> i32 {
+    // A windows-subsystem binary has no console of its own; borrow the one it
+    // was started from so output is visible.
+    #[cfg(windows)]
+    unsafe {
+        winapi::um::wincon::AttachConsole(winapi::um::wincon::ATTACH_PARENT_PROCESS);
+    }
+
+    let options = match parse_cli_args(args) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", CLI_USAGE);
+            return 0;
+        }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, CLI_USAGE);
+            return 2;
+        }
+    };
+
+    let result = extract(&options, |message| match message {
+        Message::Log(text) => print!("{}", text),
+        Message::Warning(text) => eprintln!("Warning: {}", text),
+        Message::Error(text) => eprintln!("Error: {}", text),
+        _ => {}
+    });
+    match result {
+        Ok(summary) if summary.archives_failed == 0 && summary.files_failed == 0 => 0,
+        Ok(_) => 1,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            1
+        }
     }
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        process::exit(run_headless(&args));
+    }
+
     let icon_path = Path::new("icon.ico");
 
     let icon_data = if icon_path.exists() {
@@ -279,10 +2393,19 @@ fn main() {
     let mut native_options = eframe::NativeOptions::default();
     // Set the icon via the viewport's icon field.
     native_options.viewport.icon = Some(Arc::new(icon_data));
+    // Window size and position are saved on close in the same storage as the
+    // settings and restored on the next launch. eframe moves a restored window
+    // back onto a monitor if it would be off-screen; the size below is used on
+    // the first launch.
+    native_options.persist_window = true;
+    native_options.viewport = native_options
+        .viewport
+        .with_inner_size([720.0, 900.0])
+        .with_min_inner_size([480.0, 360.0]);
 
     let _ = eframe::run_native(
-        "Zip File Extractor",
+        APP_TITLE,
         native_options,
-        Box::new(|_cc| Ok(Box::new(MyApp::default()))),
+        Box::new(|cc| Ok(Box::new(MyApp::new(cc)))),
     );
-}
\ No newline at end of file
+}